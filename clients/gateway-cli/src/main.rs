@@ -2,10 +2,16 @@
 
 use std::error::Error;
 
+use borsh::BorshDeserialize;
 use clap::{Parser, Subcommand};
 use solagate::{
-    instruction::GatewayInstruction,
-    state::{consumer_pda, gateway_pda},
+    instruction::{BatchConsumeEntry, GatewayInstruction},
+    pricing::{PricePoint, PricingModel, VolumeDiscountTier, MAX_PRICE_POINTS},
+    state::{
+        consumer_pda, consumer_vault_pda, gateway_pda, gateway_stats_pda, plan_pda,
+        route_policy_pda, BackendSigner, ConsumerAccount, ConsumerOverrides, GatewayConfig,
+        QuotaMode,
+    },
 };
 use solana_client::rpc_client::RpcClient;
 use solana_sdk::{
@@ -37,7 +43,7 @@ struct Cli {
 #[derive(Debug, Subcommand)]
 enum Commands {
     DeriveGateway {
-        admin: Pubkey,
+        creator: Pubkey,
     },
     DeriveConsumer {
         gateway: Pubkey,
@@ -62,6 +68,9 @@ enum Commands {
     Topup {
         consumer: Pubkey,
         lamports: u64,
+        /// Source token account, for gateways that bill in an SPL mint.
+        #[arg(long)]
+        token_account: Option<Pubkey>,
     },
     Consume {
         gateway: Pubkey,
@@ -69,13 +78,244 @@ enum Commands {
         treasury: Pubkey,
         api_key_id: u64,
         api_key: String,
+        /// Cost weight of the call.
+        #[arg(long, default_value_t = 1)]
+        units: u64,
+        /// Price and throttle the call with this route's policy.
+        #[arg(long)]
+        route_id: Option<u64>,
+    },
+    UpdateGateway {
+        gateway: Pubkey,
+        #[arg(long)]
+        base_price_lamports: Option<u64>,
+        #[arg(long)]
+        max_surge_bps: Option<u16>,
+        #[arg(long)]
+        period_limit: Option<u64>,
+        #[arg(long)]
+        period_seconds: Option<i64>,
+        #[arg(long)]
+        bucket_capacity: Option<u64>,
+        #[arg(long)]
+        refill_per_second: Option<u64>,
+    },
+    ProposeAdmin {
+        gateway: Pubkey,
+        new_admin: Pubkey,
+    },
+    AcceptAdmin {
+        gateway: Pubkey,
+    },
+    /// Replace the backend signer allowlist. Each entry is `PUBKEY` or `PUBKEY@EXPIRES_UNIX_TS`.
+    SetBackendSigners {
+        gateway: Pubkey,
+        #[arg(value_parser = parse_backend_signer)]
+        signers: Vec<BackendSigner>,
+    },
+    PauseGateway {
+        gateway: Pubkey,
+    },
+    ResumeGateway {
+        gateway: Pubkey,
+    },
+    Withdraw {
+        consumer: Pubkey,
+        lamports: u64,
+        /// Destination token account, for gateways that bill in an SPL mint.
+        #[arg(long)]
+        token_account: Option<Pubkey>,
+    },
+    CloseConsumer {
+        gateway: Pubkey,
+        consumer: Pubkey,
+    },
+    RotateApiKey {
+        consumer: Pubkey,
+        new_api_key: String,
+        /// How long the old key keeps working after rotation.
+        #[arg(long, default_value_t = 0)]
+        grace_seconds: i64,
+    },
+    SuspendConsumer {
+        gateway: Pubkey,
+        consumer: Pubkey,
+        /// Revoke permanently instead of suspending.
+        #[arg(long)]
+        revoke: bool,
+    },
+    ReinstateConsumer {
+        gateway: Pubkey,
+        consumer: Pubkey,
+    },
+    CloseGateway {
+        gateway: Pubkey,
+    },
+    /// Upgrade a gateway, consumer, route policy, plan or stats account to the current layout version.
+    MigrateAccount {
+        account: Pubkey,
+    },
+    /// Charge several consumers in one transaction. Each entry is
+    /// `CONSUMER_PDA:API_KEY_ID:API_KEY[:UNITS[:ROUTE_ID]]`.
+    ConsumeBatch {
+        gateway: Pubkey,
+        treasury: Pubkey,
+        #[arg(value_parser = parse_batch_entry, required = true)]
+        entries: Vec<BatchEntryArg>,
+    },
+    SetRoutePolicy {
+        gateway: Pubkey,
+        route_id: u64,
+        base_price_lamports: u64,
+        max_surge_bps: u16,
+        period_limit: u64,
+        period_seconds: i64,
+        bucket_capacity: u64,
+        refill_per_second: u64,
+    },
+    CloseRoutePolicy {
+        gateway: Pubkey,
+        route_id: u64,
+    },
+    SetPlan {
+        gateway: Pubkey,
+        plan_id: u64,
+        base_price_lamports: u64,
+        max_surge_bps: u16,
+        period_limit: u64,
+        period_seconds: i64,
+        bucket_capacity: u64,
+        refill_per_second: u64,
+    },
+    ClosePlan {
+        gateway: Pubkey,
+        plan_id: u64,
+    },
+    /// Assign a consumer to a plan; omit `--plan-id` to fall back to the gateway rules.
+    ChangePlan {
+        gateway: Pubkey,
+        consumer: Pubkey,
+        #[arg(long)]
+        plan_id: Option<u64>,
+    },
+    /// Set negotiated terms for one consumer; omitted options clear that override.
+    SetConsumerOverrides {
+        gateway: Pubkey,
+        consumer: Pubkey,
+        #[arg(long, default_value_t = 0)]
+        discount_bps: u16,
+        #[arg(long, default_value_t = 0)]
+        bucket_capacity: u64,
+        #[arg(long, default_value_t = 0)]
+        refill_per_second: u64,
+        #[arg(long, default_value_t = 0)]
+        period_limit: u64,
+    },
+    /// Set the price curve: `linear`, `flat`, `exp:GROWTH_BPS:STEP_BPS`,
+    /// `steps:UTIL_BPS=MULT_BPS,...` or `piecewise:UTIL_BPS=MULT_BPS,...`.
+    SetPricingModel {
+        gateway: Pubkey,
+        #[arg(value_parser = parse_pricing_model)]
+        model: PricingModel,
     },
+    /// Replace the lifetime volume discounts. Each tier is `MIN_TOTAL_CALLS=DISCOUNT_BPS`;
+    /// pass none to clear them.
+    SetVolumeDiscounts {
+        gateway: Pubkey,
+        #[arg(value_parser = parse_volume_tier)]
+        tiers: Vec<VolumeDiscountTier>,
+    },
+    /// Refill `refill_milli_tokens` thousandths of a token every `refill_interval_seconds`
+    /// (e.g. `1000 10` = one call per 10 s). `0 0` restores whole-token refill.
+    SetFractionalRefill {
+        gateway: Pubkey,
+        refill_milli_tokens: u64,
+        refill_interval_seconds: i64,
+        /// Apply to this plan instead of the gateway.
+        #[arg(long)]
+        plan_id: Option<u64>,
+    },
+    /// Count the period quota as a `fixed` or `sliding` window.
+    SetQuotaMode {
+        gateway: Pubkey,
+        #[arg(value_parser = parse_quota_mode)]
+        mode: QuotaMode,
+    },
+    /// Cap calls across all consumers of the gateway; all zeros disables the cap.
+    SetGatewayCapacity {
+        gateway: Pubkey,
+        period_limit: u64,
+        period_seconds: i64,
+        bucket_capacity: u64,
+        refill_per_second: u64,
+    },
+    /// Let a consumer run up to `credit_limit_lamports` of debt beyond its balance.
+    SetCreditLimit {
+        gateway: Pubkey,
+        consumer: Pubkey,
+        credit_limit_lamports: u64,
+    },
+    /// Pay down the consumer's debt; any surplus tops up its balance.
+    SettleDebt {
+        gateway: Pubkey,
+        consumer: Pubkey,
+        treasury: Pubkey,
+        lamports: u64,
+        /// Source token account, for gateways that bill in an SPL mint.
+        #[arg(long)]
+        token_account: Option<Pubkey>,
+    },
+    /// Bill in `mint` instead of lamports; only allowed before any consumer registers.
+    SetBillingMint {
+        gateway: Pubkey,
+        mint: Pubkey,
+        /// Token account of `mint` that receives charges.
+        treasury_token_account: Pubkey,
+    },
+    /// Create the consumer's token vault on a gateway that bills in an SPL mint.
+    OpenTokenVault {
+        gateway: Pubkey,
+        consumer: Pubkey,
+    },
+    /// Serve `free_calls_per_period` units free each period, then `trial_calls`
+    /// lifetime units, before charging.
+    SetFreeAllowance {
+        gateway: Pubkey,
+        free_calls_per_period: u64,
+        trial_calls: u64,
+        /// Apply to this plan instead of the gateway.
+        #[arg(long)]
+        plan_id: Option<u64>,
+    },
+    /// Price calls by gateway-wide load: `target_calls` units over the trailing
+    /// `window_seconds` is full surge. A target of `0` restores quota pricing.
+    SetCongestionPricing {
+        gateway: Pubkey,
+        window_seconds: i64,
+        target_calls: u64,
+    },
+    /// Limit consumers with GCRA: one call per `emission_interval_ms`, running up to
+    /// `burst_tolerance_ms` ahead. An interval of `0` restores the token bucket.
+    SetGcraLimiter {
+        gateway: Pubkey,
+        emission_interval_ms: u64,
+        burst_tolerance_ms: u64,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct BatchEntryArg {
+    consumer: Pubkey,
+    api_key_id: u64,
+    api_key: String,
+    units: u64,
+    route_id: Option<u64>,
 }
 
 fn run(cli: Cli) -> Result<(), Box<dyn Error>> {
     match cli.command {
-        Commands::DeriveGateway { admin } => {
-            let (gateway, bump) = gateway_pda(&admin, &cli.program_id);
+        Commands::DeriveGateway { creator } => {
+            let (gateway, bump) = gateway_pda(&creator, &cli.program_id);
             println!("gateway_pda={gateway}");
             println!("bump={bump}");
             Ok(())
@@ -156,22 +396,31 @@ fn run_online_command(
                 program_id,
                 accounts: vec![
                     AccountMeta::new(signer.pubkey(), true),
-                    AccountMeta::new_readonly(gateway, false),
+                    AccountMeta::new(gateway, false),
                     AccountMeta::new(consumer, false),
                     AccountMeta::new_readonly(system_program::id(), false),
                 ],
                 data,
             }
         }
-        Commands::Topup { consumer, lamports } => {
+        Commands::Topup {
+            consumer,
+            lamports,
+            token_account,
+        } => {
             let data = GatewayInstruction::TopUp { lamports }.pack()?;
+            let gateway = fetch_consumer(&rpc, &consumer)?.gateway;
+            let mut accounts = vec![
+                AccountMeta::new(signer.pubkey(), true),
+                AccountMeta::new(consumer, false),
+                AccountMeta::new_readonly(gateway, false),
+                AccountMeta::new_readonly(system_program::id(), false),
+            ];
+            accounts.extend(vault_transfer_accounts(&rpc, &consumer, token_account)?);
+
             Instruction {
                 program_id,
-                accounts: vec![
-                    AccountMeta::new(signer.pubkey(), true),
-                    AccountMeta::new(consumer, false),
-                    AccountMeta::new_readonly(system_program::id(), false),
-                ],
+                accounts,
                 data,
             }
         }
@@ -181,10 +430,57 @@ fn run_online_command(
             treasury,
             api_key_id,
             api_key,
+            units,
+            route_id,
         } => {
             let data = GatewayInstruction::Consume {
                 api_key_id,
                 presented_api_key_hash: api_key_hash(&api_key),
+                units,
+                route_id,
+            }
+            .pack()?;
+
+            let mut accounts = vec![
+                AccountMeta::new_readonly(signer.pubkey(), true),
+                AccountMeta::new_readonly(gateway, false),
+                AccountMeta::new(consumer, false),
+                AccountMeta::new(treasury, false),
+            ];
+            if let Some(route_id) = route_id {
+                let (route, _) = route_policy_pda(&gateway, route_id, &program_id);
+                accounts.push(AccountMeta::new(route, false));
+            }
+            if let Some(plan) = assigned_plan(&rpc, &consumer)? {
+                accounts.push(AccountMeta::new_readonly(plan, false));
+            }
+            if let Some(stats) = gateway_stats(&rpc, &gateway)? {
+                accounts.push(AccountMeta::new(stats, false));
+            }
+            accounts.extend(vault_charge_accounts(&rpc, &consumer)?);
+
+            Instruction {
+                program_id,
+                accounts,
+                data,
+            }
+        }
+        Commands::UpdateGateway {
+            gateway,
+            base_price_lamports,
+            max_surge_bps,
+            period_limit,
+            period_seconds,
+            bucket_capacity,
+            refill_per_second,
+        } => {
+            let data = GatewayInstruction::UpdateGatewayConfig {
+                base_price_lamports,
+                max_surge_bps,
+                period_limit,
+                period_seconds,
+                bucket_capacity,
+                refill_per_second,
             }
             .pack()?;
 
@@ -192,102 +488,1034 @@ fn run_online_command(
                 program_id,
                 accounts: vec![
                     AccountMeta::new_readonly(signer.pubkey(), true),
-                    AccountMeta::new_readonly(gateway, false),
-                    AccountMeta::new(consumer, false),
-                    AccountMeta::new(treasury, false),
+                    AccountMeta::new(gateway, false),
                 ],
                 data,
             }
         }
-        Commands::DeriveGateway { .. } | Commands::DeriveConsumer { .. } => {
-            return Err("internal error: derive command routed to online path".into());
+        Commands::ProposeAdmin { gateway, new_admin } => {
+            let data = GatewayInstruction::ProposeAdmin { new_admin }.pack()?;
+            Instruction {
+                program_id,
+                accounts: vec![
+                    AccountMeta::new_readonly(signer.pubkey(), true),
+                    AccountMeta::new(gateway, false),
+                ],
+                data,
+            }
         }
-    };
-
-    let sig = send_instruction(&rpc, signer, ix)?;
-    println!("signature={sig}");
-    println!("explorer=https://explorer.solana.com/tx/{sig}?cluster=devnet");
-    Ok(())
-}
-
-fn send_instruction(
-    rpc: &RpcClient,
-    signer: &Keypair,
-    instruction: Instruction,
-) -> Result<solana_sdk::signature::Signature, Box<dyn Error>> {
-    let recent_blockhash = rpc.get_latest_blockhash()?;
-    let tx = Transaction::new_signed_with_payer(
-        &[instruction],
-        Some(&signer.pubkey()),
-        &[signer],
-        recent_blockhash,
-    );
-
-    let sig = rpc.send_and_confirm_transaction(&tx)?;
-    Ok(sig)
-}
-
-fn api_key_hash(input: &str) -> [u8; 32] {
-    hash(input.as_bytes()).to_bytes()
-}
-
-fn main() -> Result<(), Box<dyn Error>> {
-    let cli = Cli::parse();
-    run(cli)
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn parses_derive_consumer_command() {
-        let gateway = Pubkey::new_unique();
-        let owner = Pubkey::new_unique();
-        let program_id = Pubkey::new_unique();
-
-        let cli = Cli::parse_from([
-            "solagate-cli",
-            "--program-id",
-            &program_id.to_string(),
-            "--keypair",
-            "/tmp/dummy.json",
-            "derive-consumer",
-            &gateway.to_string(),
-            &owner.to_string(),
-            "7",
-        ]);
+        Commands::AcceptAdmin { gateway } => {
+            let data = GatewayInstruction::AcceptAdmin.pack()?;
+            Instruction {
+                program_id,
+                accounts: vec![
+                    AccountMeta::new_readonly(signer.pubkey(), true),
+                    AccountMeta::new(gateway, false),
+                ],
+                data,
+            }
+        }
+        Commands::SetBackendSigners { gateway, signers } => {
+            let data = GatewayInstruction::SetBackendSigners { signers }.pack()?;
+            Instruction {
+                program_id,
+                accounts: vec![
+                    AccountMeta::new_readonly(signer.pubkey(), true),
+                    AccountMeta::new(gateway, false),
+                ],
+                data,
+            }
+        }
+        Commands::PauseGateway { gateway } => {
+            let data = GatewayInstruction::PauseGateway.pack()?;
+            Instruction {
+                program_id,
+                accounts: vec![
+                    AccountMeta::new_readonly(signer.pubkey(), true),
+                    AccountMeta::new(gateway, false),
+                ],
+                data,
+            }
+        }
+        Commands::ResumeGateway { gateway } => {
+            let data = GatewayInstruction::ResumeGateway.pack()?;
+            Instruction {
+                program_id,
+                accounts: vec![
+                    AccountMeta::new_readonly(signer.pubkey(), true),
+                    AccountMeta::new(gateway, false),
+                ],
+                data,
+            }
+        }
+        Commands::Withdraw {
+            consumer,
+            lamports,
+            token_account,
+        } => {
+            let data = GatewayInstruction::Withdraw { lamports }.pack()?;
+            let mut accounts = vec![
+                AccountMeta::new(signer.pubkey(), true),
+                AccountMeta::new(consumer, false),
+            ];
+            accounts.extend(vault_transfer_accounts(&rpc, &consumer, token_account)?);
 
-        match cli.command {
-            Commands::DeriveConsumer {
-                gateway: parsed_gateway,
-                owner: parsed_owner,
-                api_key_id,
-            } => {
-                assert_eq!(parsed_gateway, gateway);
-                assert_eq!(parsed_owner, owner);
-                assert_eq!(api_key_id, 7);
+            Instruction {
+                program_id,
+                accounts,
+                data,
             }
-            _ => panic!("wrong command variant"),
         }
-    }
+        Commands::CloseConsumer { gateway, consumer } => {
+            let data = GatewayInstruction::CloseConsumer.pack()?;
+            let mut accounts = vec![
+                AccountMeta::new(signer.pubkey(), true),
+                AccountMeta::new(consumer, false),
+                AccountMeta::new(gateway, false),
+            ];
+            if let Some(plan) = assigned_plan(&rpc, &consumer)? {
+                accounts.push(AccountMeta::new(plan, false));
+            }
+            let account = fetch_consumer(&rpc, &consumer)?;
+            if account.has_token_vault() {
+                let token_program = rpc.get_account(&account.token_vault)?.owner;
+                let mint = fetch_gateway(&rpc, &gateway)?.billing_mint;
+                accounts.push(AccountMeta::new(account.token_vault, false));
+                accounts.push(AccountMeta::new(mint, false));
+                accounts.push(AccountMeta::new_readonly(token_program, false));
+            }
 
-    #[test]
+            Instruction {
+                program_id,
+                accounts,
+                data,
+            }
+        }
+        Commands::RotateApiKey {
+            consumer,
+            new_api_key,
+            grace_seconds,
+        } => {
+            let data = GatewayInstruction::RotateApiKey {
+                new_hash: api_key_hash(&new_api_key),
+                grace_seconds,
+            }
+            .pack()?;
+            Instruction {
+                program_id,
+                accounts: vec![
+                    AccountMeta::new_readonly(signer.pubkey(), true),
+                    AccountMeta::new(consumer, false),
+                ],
+                data,
+            }
+        }
+        Commands::SuspendConsumer {
+            gateway,
+            consumer,
+            revoke,
+        } => {
+            let data = GatewayInstruction::SuspendConsumer { revoke }.pack()?;
+            Instruction {
+                program_id,
+                accounts: vec![
+                    AccountMeta::new_readonly(signer.pubkey(), true),
+                    AccountMeta::new_readonly(gateway, false),
+                    AccountMeta::new(consumer, false),
+                ],
+                data,
+            }
+        }
+        Commands::ReinstateConsumer { gateway, consumer } => {
+            let data = GatewayInstruction::ReinstateConsumer.pack()?;
+            Instruction {
+                program_id,
+                accounts: vec![
+                    AccountMeta::new_readonly(signer.pubkey(), true),
+                    AccountMeta::new_readonly(gateway, false),
+                    AccountMeta::new(consumer, false),
+                ],
+                data,
+            }
+        }
+        Commands::CloseGateway { gateway } => {
+            let data = GatewayInstruction::CloseGateway.pack()?;
+            let mut accounts = vec![
+                AccountMeta::new(signer.pubkey(), true),
+                AccountMeta::new(gateway, false),
+            ];
+            if let Some(stats) = gateway_stats(&rpc, &gateway)? {
+                accounts.push(AccountMeta::new(stats, false));
+            }
+
+            Instruction {
+                program_id,
+                accounts,
+                data,
+            }
+        }
+        Commands::MigrateAccount { account } => {
+            let data = GatewayInstruction::MigrateAccount.pack()?;
+            let mut accounts = vec![
+                AccountMeta::new(signer.pubkey(), true),
+                AccountMeta::new(account, false),
+                AccountMeta::new_readonly(system_program::id(), false),
+            ];
+            // A baseline consumer adds itself to its gateway's consumer count.
+            let stored = rpc.get_account_data(&account)?;
+            if stored.len() == ConsumerAccount::BASELINE_LEN {
+                let gateway = Pubkey::try_from(&stored[1..33])?;
+                accounts.push(AccountMeta::new(gateway, false));
+            }
+
+            Instruction {
+                program_id,
+                accounts,
+                data,
+            }
+        }
+        Commands::ConsumeBatch {
+            gateway,
+            treasury,
+            entries,
+        } => {
+            let mut accounts = vec![
+                AccountMeta::new_readonly(signer.pubkey(), true),
+                AccountMeta::new_readonly(gateway, false),
+                AccountMeta::new(treasury, false),
+            ];
+            accounts.extend(
+                entries
+                    .iter()
+                    .map(|entry| AccountMeta::new(entry.consumer, false)),
+            );
+            let mut route_ids: Vec<u64> = entries.iter().filter_map(|e| e.route_id).collect();
+            route_ids.sort_unstable();
+            route_ids.dedup();
+            accounts.extend(route_ids.into_iter().map(|route_id| {
+                AccountMeta::new(route_policy_pda(&gateway, route_id, &program_id).0, false)
+            }));
+            let mut plans = Vec::new();
+            for entry in &entries {
+                if let Some(plan) = assigned_plan(&rpc, &entry.consumer)? {
+                    plans.push(plan);
+                }
+            }
+            plans.sort_unstable();
+            plans.dedup();
+            accounts.extend(
+                plans
+                    .into_iter()
+                    .map(|plan| AccountMeta::new_readonly(plan, false)),
+            );
+            if let Some(stats) = gateway_stats(&rpc, &gateway)? {
+                accounts.push(AccountMeta::new(stats, false));
+            }
+            let mut billing_accounts: Vec<AccountMeta> = Vec::new();
+            for entry in &entries {
+                for meta in vault_charge_accounts(&rpc, &entry.consumer)? {
+                    if !billing_accounts.iter().any(|m| m.pubkey == meta.pubkey) {
+                        billing_accounts.push(meta);
+                    }
+                }
+            }
+            accounts.extend(billing_accounts);
+
+            let data = GatewayInstruction::ConsumeBatch {
+                entries: entries
+                    .iter()
+                    .map(|entry| BatchConsumeEntry {
+                        api_key_id: entry.api_key_id,
+                        presented_api_key_hash: api_key_hash(&entry.api_key),
+                        units: entry.units,
+                        route_id: entry.route_id,
+                    })
+                    .collect(),
+            }
+            .pack()?;
+
+            Instruction {
+                program_id,
+                accounts,
+                data,
+            }
+        }
+        Commands::SetRoutePolicy {
+            gateway,
+            route_id,
+            base_price_lamports,
+            max_surge_bps,
+            period_limit,
+            period_seconds,
+            bucket_capacity,
+            refill_per_second,
+        } => {
+            let (route, _) = route_policy_pda(&gateway, route_id, &program_id);
+            let data = GatewayInstruction::SetRoutePolicy {
+                route_id,
+                base_price_lamports,
+                max_surge_bps,
+                period_limit,
+                period_seconds,
+                bucket_capacity,
+                refill_per_second,
+            }
+            .pack()?;
+
+            Instruction {
+                program_id,
+                accounts: vec![
+                    AccountMeta::new(signer.pubkey(), true),
+                    AccountMeta::new(gateway, false),
+                    AccountMeta::new(route, false),
+                    AccountMeta::new_readonly(system_program::id(), false),
+                ],
+                data,
+            }
+        }
+        Commands::CloseRoutePolicy { gateway, route_id } => {
+            let (route, _) = route_policy_pda(&gateway, route_id, &program_id);
+            let data = GatewayInstruction::CloseRoutePolicy { route_id }.pack()?;
+            Instruction {
+                program_id,
+                accounts: vec![
+                    AccountMeta::new(signer.pubkey(), true),
+                    AccountMeta::new(gateway, false),
+                    AccountMeta::new(route, false),
+                ],
+                data,
+            }
+        }
+        Commands::SetPlan {
+            gateway,
+            plan_id,
+            base_price_lamports,
+            max_surge_bps,
+            period_limit,
+            period_seconds,
+            bucket_capacity,
+            refill_per_second,
+        } => {
+            let (plan, _) = plan_pda(&gateway, plan_id, &program_id);
+            let data = GatewayInstruction::SetPlan {
+                plan_id,
+                base_price_lamports,
+                max_surge_bps,
+                period_limit,
+                period_seconds,
+                bucket_capacity,
+                refill_per_second,
+            }
+            .pack()?;
+
+            Instruction {
+                program_id,
+                accounts: vec![
+                    AccountMeta::new(signer.pubkey(), true),
+                    AccountMeta::new(gateway, false),
+                    AccountMeta::new(plan, false),
+                    AccountMeta::new_readonly(system_program::id(), false),
+                ],
+                data,
+            }
+        }
+        Commands::ClosePlan { gateway, plan_id } => {
+            let (plan, _) = plan_pda(&gateway, plan_id, &program_id);
+            let data = GatewayInstruction::ClosePlan { plan_id }.pack()?;
+            Instruction {
+                program_id,
+                accounts: vec![
+                    AccountMeta::new(signer.pubkey(), true),
+                    AccountMeta::new(gateway, false),
+                    AccountMeta::new(plan, false),
+                ],
+                data,
+            }
+        }
+        Commands::ChangePlan {
+            gateway,
+            consumer,
+            plan_id,
+        } => {
+            let data = GatewayInstruction::ChangePlan { plan_id }.pack()?;
+            let mut accounts = vec![
+                AccountMeta::new_readonly(signer.pubkey(), true),
+                AccountMeta::new_readonly(gateway, false),
+                AccountMeta::new(consumer, false),
+            ];
+            if let Some(current) = assigned_plan(&rpc, &consumer)? {
+                accounts.push(AccountMeta::new(current, false));
+            }
+            if let Some(plan_id) = plan_id {
+                let (plan, _) = plan_pda(&gateway, plan_id, &program_id);
+                accounts.push(AccountMeta::new(plan, false));
+            }
+
+            Instruction {
+                program_id,
+                accounts,
+                data,
+            }
+        }
+        Commands::SetConsumerOverrides {
+            gateway,
+            consumer,
+            discount_bps,
+            bucket_capacity,
+            refill_per_second,
+            period_limit,
+        } => {
+            let data = GatewayInstruction::SetConsumerOverrides {
+                overrides: ConsumerOverrides {
+                    discount_bps,
+                    bucket_capacity,
+                    refill_per_second,
+                    period_limit,
+                },
+            }
+            .pack()?;
+            Instruction {
+                program_id,
+                accounts: vec![
+                    AccountMeta::new_readonly(signer.pubkey(), true),
+                    AccountMeta::new_readonly(gateway, false),
+                    AccountMeta::new(consumer, false),
+                ],
+                data,
+            }
+        }
+        Commands::SetPricingModel { gateway, model } => {
+            let data = GatewayInstruction::SetPricingModel { model }.pack()?;
+            Instruction {
+                program_id,
+                accounts: vec![
+                    AccountMeta::new_readonly(signer.pubkey(), true),
+                    AccountMeta::new(gateway, false),
+                ],
+                data,
+            }
+        }
+        Commands::SetVolumeDiscounts { gateway, tiers } => {
+            let data = GatewayInstruction::SetVolumeDiscounts { tiers }.pack()?;
+            Instruction {
+                program_id,
+                accounts: vec![
+                    AccountMeta::new_readonly(signer.pubkey(), true),
+                    AccountMeta::new(gateway, false),
+                ],
+                data,
+            }
+        }
+        Commands::SetFractionalRefill {
+            gateway,
+            refill_milli_tokens,
+            refill_interval_seconds,
+            plan_id,
+        } => {
+            let data = GatewayInstruction::SetFractionalRefill {
+                plan_id,
+                refill_milli_tokens,
+                refill_interval_seconds,
+            }
+            .pack()?;
+            let mut accounts = vec![
+                AccountMeta::new_readonly(signer.pubkey(), true),
+                AccountMeta::new(gateway, false),
+            ];
+            if let Some(plan_id) = plan_id {
+                let (plan, _) = plan_pda(&gateway, plan_id, &program_id);
+                accounts.push(AccountMeta::new(plan, false));
+            }
+
+            Instruction {
+                program_id,
+                accounts,
+                data,
+            }
+        }
+        Commands::SetQuotaMode { gateway, mode } => {
+            let data = GatewayInstruction::SetQuotaMode { mode }.pack()?;
+
+            Instruction {
+                program_id,
+                accounts: vec![
+                    AccountMeta::new_readonly(signer.pubkey(), true),
+                    AccountMeta::new(gateway, false),
+                ],
+                data,
+            }
+        }
+        Commands::SetGatewayCapacity {
+            gateway,
+            period_limit,
+            period_seconds,
+            bucket_capacity,
+            refill_per_second,
+        } => {
+            let (stats, _) = gateway_stats_pda(&gateway, &program_id);
+            let data = GatewayInstruction::SetGatewayCapacity {
+                period_limit,
+                period_seconds,
+                bucket_capacity,
+                refill_per_second,
+            }
+            .pack()?;
+
+            Instruction {
+                program_id,
+                accounts: vec![
+                    AccountMeta::new(signer.pubkey(), true),
+                    AccountMeta::new(gateway, false),
+                    AccountMeta::new(stats, false),
+                    AccountMeta::new_readonly(system_program::id(), false),
+                ],
+                data,
+            }
+        }
+        Commands::SetCreditLimit {
+            gateway,
+            consumer,
+            credit_limit_lamports,
+        } => {
+            let data = GatewayInstruction::SetCreditLimit {
+                credit_limit_lamports,
+            }
+            .pack()?;
+
+            Instruction {
+                program_id,
+                accounts: vec![
+                    AccountMeta::new_readonly(signer.pubkey(), true),
+                    AccountMeta::new_readonly(gateway, false),
+                    AccountMeta::new(consumer, false),
+                ],
+                data,
+            }
+        }
+        Commands::SettleDebt {
+            gateway,
+            consumer,
+            treasury,
+            lamports,
+            token_account,
+        } => {
+            let data = GatewayInstruction::SettleDebt { lamports }.pack()?;
+            let mut accounts = vec![
+                AccountMeta::new(signer.pubkey(), true),
+                AccountMeta::new(consumer, false),
+                AccountMeta::new_readonly(gateway, false),
+                AccountMeta::new(treasury, false),
+                AccountMeta::new_readonly(system_program::id(), false),
+            ];
+            accounts.extend(vault_transfer_accounts(&rpc, &consumer, token_account)?);
+
+            Instruction {
+                program_id,
+                accounts,
+                data,
+            }
+        }
+        Commands::SetBillingMint {
+            gateway,
+            mint,
+            treasury_token_account,
+        } => {
+            let data = GatewayInstruction::SetBillingMint.pack()?;
+
+            Instruction {
+                program_id,
+                accounts: vec![
+                    AccountMeta::new_readonly(signer.pubkey(), true),
+                    AccountMeta::new(gateway, false),
+                    AccountMeta::new_readonly(mint, false),
+                    AccountMeta::new_readonly(treasury_token_account, false),
+                ],
+                data,
+            }
+        }
+        Commands::OpenTokenVault { gateway, consumer } => {
+            let mint = fetch_gateway(&rpc, &gateway)?.billing_mint;
+            let token_program = rpc.get_account(&mint)?.owner;
+            let (vault, _) = consumer_vault_pda(&consumer, &program_id);
+            let data = GatewayInstruction::OpenTokenVault.pack()?;
+
+            Instruction {
+                program_id,
+                accounts: vec![
+                    AccountMeta::new(signer.pubkey(), true),
+                    AccountMeta::new_readonly(gateway, false),
+                    AccountMeta::new(consumer, false),
+                    AccountMeta::new(vault, false),
+                    AccountMeta::new_readonly(mint, false),
+                    AccountMeta::new_readonly(token_program, false),
+                    AccountMeta::new_readonly(system_program::id(), false),
+                ],
+                data,
+            }
+        }
+        Commands::SetFreeAllowance {
+            gateway,
+            free_calls_per_period,
+            trial_calls,
+            plan_id,
+        } => {
+            let data = GatewayInstruction::SetFreeAllowance {
+                plan_id,
+                free_calls_per_period,
+                trial_calls,
+            }
+            .pack()?;
+            let mut accounts = vec![
+                AccountMeta::new_readonly(signer.pubkey(), true),
+                AccountMeta::new(gateway, false),
+            ];
+            if let Some(plan_id) = plan_id {
+                let (plan, _) = plan_pda(&gateway, plan_id, &program_id);
+                accounts.push(AccountMeta::new(plan, false));
+            }
+
+            Instruction {
+                program_id,
+                accounts,
+                data,
+            }
+        }
+        Commands::SetCongestionPricing {
+            gateway,
+            window_seconds,
+            target_calls,
+        } => {
+            let (stats, _) = gateway_stats_pda(&gateway, &program_id);
+            let data = GatewayInstruction::SetCongestionPricing {
+                window_seconds,
+                target_calls,
+            }
+            .pack()?;
+
+            Instruction {
+                program_id,
+                accounts: vec![
+                    AccountMeta::new_readonly(signer.pubkey(), true),
+                    AccountMeta::new_readonly(gateway, false),
+                    AccountMeta::new(stats, false),
+                ],
+                data,
+            }
+        }
+        Commands::SetGcraLimiter {
+            gateway,
+            emission_interval_ms,
+            burst_tolerance_ms,
+        } => {
+            let data = GatewayInstruction::SetGcraLimiter {
+                emission_interval_ms,
+                burst_tolerance_ms,
+            }
+            .pack()?;
+
+            Instruction {
+                program_id,
+                accounts: vec![
+                    AccountMeta::new_readonly(signer.pubkey(), true),
+                    AccountMeta::new(gateway, false),
+                ],
+                data,
+            }
+        }
+        Commands::DeriveGateway { .. } | Commands::DeriveConsumer { .. } => {
+            return Err("internal error: derive command routed to online path".into());
+        }
+    };
+
+    let sig = send_instruction(&rpc, signer, ix)?;
+    println!("signature={sig}");
+    println!("explorer=https://explorer.solana.com/tx/{sig}?cluster=devnet");
+    Ok(())
+}
+
+fn send_instruction(
+    rpc: &RpcClient,
+    signer: &Keypair,
+    instruction: Instruction,
+) -> Result<solana_sdk::signature::Signature, Box<dyn Error>> {
+    let recent_blockhash = rpc.get_latest_blockhash()?;
+    let tx = Transaction::new_signed_with_payer(
+        &[instruction],
+        Some(&signer.pubkey()),
+        &[signer],
+        recent_blockhash,
+    );
+
+    let sig = rpc.send_and_confirm_transaction(&tx)?;
+    Ok(sig)
+}
+
+fn fetch_consumer(rpc: &RpcClient, consumer: &Pubkey) -> Result<ConsumerAccount, Box<dyn Error>> {
+    let data = rpc.get_account_data(consumer)?;
+    Ok(ConsumerAccount::try_from_slice(&data).map_err(|e| {
+        format!("failed to decode consumer {consumer} (run migrate-account if outdated): {e}")
+    })?)
+}
+
+fn fetch_gateway(rpc: &RpcClient, gateway: &Pubkey) -> Result<GatewayConfig, Box<dyn Error>> {
+    let data = rpc.get_account_data(gateway)?;
+    Ok(GatewayConfig::try_from_slice(&data).map_err(|e| {
+        format!("failed to decode gateway {gateway} (run migrate-account if outdated): {e}")
+    })?)
+}
+
+/// Plan the consumer is assigned to, which the program expects among the extra accounts.
+fn assigned_plan(rpc: &RpcClient, consumer: &Pubkey) -> Result<Option<Pubkey>, Box<dyn Error>> {
+    let account = fetch_consumer(rpc, consumer)?;
+    Ok(account.has_plan().then_some(account.plan))
+}
+
+fn gateway_stats(rpc: &RpcClient, gateway: &Pubkey) -> Result<Option<Pubkey>, Box<dyn Error>> {
+    let account = fetch_gateway(rpc, gateway)?;
+    Ok(account.has_stats().then_some(account.stats))
+}
+
+/// Vault, billing mint and its token program (Token or Token-2022) a charge
+/// against the consumer's token vault needs; empty for lamport billing.
+fn vault_charge_accounts(
+    rpc: &RpcClient,
+    consumer: &Pubkey,
+) -> Result<Vec<AccountMeta>, Box<dyn Error>> {
+    let account = fetch_consumer(rpc, consumer)?;
+    if !account.has_token_vault() {
+        return Ok(Vec::new());
+    }
+    let mint = fetch_gateway(rpc, &account.gateway)?.billing_mint;
+    let token_program = rpc.get_account(&mint)?.owner;
+    Ok(vec![
+        AccountMeta::new(account.token_vault, false),
+        AccountMeta::new_readonly(mint, false),
+        AccountMeta::new_readonly(token_program, false),
+    ])
+}
+
+/// Trailing accounts for moving tokens between `token_account` and the
+/// consumer's vault; empty for lamport billing.
+fn vault_transfer_accounts(
+    rpc: &RpcClient,
+    consumer: &Pubkey,
+    token_account: Option<Pubkey>,
+) -> Result<Vec<AccountMeta>, Box<dyn Error>> {
+    let vault_accounts = vault_charge_accounts(rpc, consumer)?;
+    match (token_account, vault_accounts.is_empty()) {
+        (None, true) => Ok(Vec::new()),
+        (Some(token_account), false) => Ok(std::iter::once(AccountMeta::new(token_account, false))
+            .chain(vault_accounts)
+            .collect()),
+        (None, false) => {
+            Err(format!("consumer {consumer} bills in tokens; pass --token-account").into())
+        }
+        (Some(_), true) => Err(format!("consumer {consumer} has no token vault").into()),
+    }
+}
+
+fn parse_backend_signer(input: &str) -> Result<BackendSigner, String> {
+    let (key, expires_at_ts) = match input.split_once('@') {
+        Some((key, ts)) => (
+            key,
+            ts.parse::<i64>()
+                .map_err(|e| format!("invalid expiry timestamp {ts}: {e}"))?,
+        ),
+        None => (input, 0),
+    };
+    let signer = key
+        .parse::<Pubkey>()
+        .map_err(|e| format!("invalid signer pubkey {key}: {e}"))?;
+    Ok(BackendSigner {
+        signer,
+        expires_at_ts,
+    })
+}
+
+fn parse_batch_entry(input: &str) -> Result<BatchEntryArg, String> {
+    let parts: Vec<&str> = input.split(':').collect();
+    let (consumer, api_key_id, api_key, units, route_id) = match parts.as_slice() {
+        [consumer, id, key] => (*consumer, *id, *key, "1", None),
+        [consumer, id, key, units] => (*consumer, *id, *key, *units, None),
+        [consumer, id, key, units, route] => (*consumer, *id, *key, *units, Some(*route)),
+        _ => {
+            return Err(format!(
+                "expected CONSUMER:API_KEY_ID:API_KEY[:UNITS[:ROUTE_ID]], got {input}"
+            ))
+        }
+    };
+
+    Ok(BatchEntryArg {
+        consumer: consumer
+            .parse::<Pubkey>()
+            .map_err(|e| format!("invalid consumer pubkey {consumer}: {e}"))?,
+        api_key_id: api_key_id
+            .parse::<u64>()
+            .map_err(|e| format!("invalid api key id {api_key_id}: {e}"))?,
+        api_key: api_key.to_string(),
+        units: units
+            .parse::<u64>()
+            .map_err(|e| format!("invalid units {units}: {e}"))?,
+        route_id: route_id
+            .map(|route| {
+                route
+                    .parse::<u64>()
+                    .map_err(|e| format!("invalid route id {route}: {e}"))
+            })
+            .transpose()?,
+    })
+}
+
+fn parse_pricing_model(input: &str) -> Result<PricingModel, String> {
+    let (kind, params) = input.split_once(':').unwrap_or((input, ""));
+    let model = match kind {
+        "linear" => PricingModel::LinearSurge,
+        "flat" => PricingModel::Flat,
+        "exp" => {
+            let (growth, step) = params
+                .split_once(':')
+                .ok_or_else(|| format!("expected exp:GROWTH_BPS:STEP_BPS, got {input}"))?;
+            PricingModel::Exponential {
+                growth_bps: growth
+                    .parse::<u32>()
+                    .map_err(|e| format!("invalid growth bps {growth}: {e}"))?,
+                step_bps: step
+                    .parse::<u16>()
+                    .map_err(|e| format!("invalid step bps {step}: {e}"))?,
+            }
+        }
+        "steps" => {
+            let (count, tiers) = parse_price_points(params)?;
+            PricingModel::StepTiers { count, tiers }
+        }
+        "piecewise" => {
+            let (count, points) = parse_price_points(params)?;
+            PricingModel::PiecewiseLinear { count, points }
+        }
+        _ => return Err(format!("unknown pricing model {kind}")),
+    };
+
+    if !model.is_valid() {
+        return Err(format!("invalid pricing model {input}"));
+    }
+    Ok(model)
+}
+
+fn parse_price_points(input: &str) -> Result<(u8, [PricePoint; MAX_PRICE_POINTS]), String> {
+    let mut points = [PricePoint::default(); MAX_PRICE_POINTS];
+    let entries: Vec<&str> = input.split(',').collect();
+    if entries.len() > MAX_PRICE_POINTS {
+        return Err(format!(
+            "at most {MAX_PRICE_POINTS} price points, got {input}"
+        ));
+    }
+
+    for (slot, entry) in points.iter_mut().zip(&entries) {
+        let (utilization, multiplier) = entry
+            .split_once('=')
+            .ok_or_else(|| format!("expected UTIL_BPS=MULT_BPS, got {entry}"))?;
+        *slot = PricePoint {
+            utilization_bps: utilization
+                .parse::<u16>()
+                .map_err(|e| format!("invalid utilization bps {utilization}: {e}"))?,
+            multiplier_bps: multiplier
+                .parse::<u32>()
+                .map_err(|e| format!("invalid multiplier bps {multiplier}: {e}"))?,
+        };
+    }
+    Ok((entries.len() as u8, points))
+}
+
+fn parse_volume_tier(input: &str) -> Result<VolumeDiscountTier, String> {
+    let (calls, discount) = input
+        .split_once('=')
+        .ok_or_else(|| format!("expected MIN_TOTAL_CALLS=DISCOUNT_BPS, got {input}"))?;
+    Ok(VolumeDiscountTier {
+        min_total_calls: calls
+            .parse::<u64>()
+            .map_err(|e| format!("invalid call count {calls}: {e}"))?,
+        discount_bps: discount
+            .parse::<u16>()
+            .map_err(|e| format!("invalid discount bps {discount}: {e}"))?,
+    })
+}
+
+fn parse_quota_mode(input: &str) -> Result<QuotaMode, String> {
+    match input {
+        "fixed" => Ok(QuotaMode::FixedWindow),
+        "sliding" => Ok(QuotaMode::SlidingWindow),
+        _ => Err(format!("expected fixed or sliding, got {input}")),
+    }
+}
+
+fn api_key_hash(input: &str) -> [u8; 32] {
+    hash(input.as_bytes()).to_bytes()
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let cli = Cli::parse();
+    run(cli)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_derive_consumer_command() {
+        let gateway = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let program_id = Pubkey::new_unique();
+
+        let cli = Cli::parse_from([
+            "solagate-cli",
+            "--program-id",
+            &program_id.to_string(),
+            "--keypair",
+            "/tmp/dummy.json",
+            "derive-consumer",
+            &gateway.to_string(),
+            &owner.to_string(),
+            "7",
+        ]);
+
+        match cli.command {
+            Commands::DeriveConsumer {
+                gateway: parsed_gateway,
+                owner: parsed_owner,
+                api_key_id,
+            } => {
+                assert_eq!(parsed_gateway, gateway);
+                assert_eq!(parsed_owner, owner);
+                assert_eq!(api_key_id, 7);
+            }
+            _ => panic!("wrong command variant"),
+        }
+    }
+
+    #[test]
+    fn parses_update_gateway_with_partial_fields() {
+        let gateway = Pubkey::new_unique();
+        let program_id = Pubkey::new_unique();
+
+        let cli = Cli::parse_from([
+            "solagate-cli",
+            "--program-id",
+            &program_id.to_string(),
+            "--keypair",
+            "/tmp/dummy.json",
+            "update-gateway",
+            &gateway.to_string(),
+            "--base-price-lamports",
+            "2500",
+            "--period-limit",
+            "500",
+        ]);
+
+        match cli.command {
+            Commands::UpdateGateway {
+                gateway: parsed_gateway,
+                base_price_lamports,
+                max_surge_bps,
+                period_limit,
+                ..
+            } => {
+                assert_eq!(parsed_gateway, gateway);
+                assert_eq!(base_price_lamports, Some(2_500));
+                assert_eq!(max_surge_bps, None);
+                assert_eq!(period_limit, Some(500));
+            }
+            _ => panic!("wrong command variant"),
+        }
+    }
+
+    #[test]
     fn run_derive_gateway_returns_ok() {
         let program_id = Pubkey::new_unique();
-        let admin = Pubkey::new_unique();
+        let creator = Pubkey::new_unique();
 
         let cli = Cli {
             rpc_url: "https://api.devnet.solana.com".into(),
             program_id,
             keypair: "/tmp/dummy.json".into(),
-            command: Commands::DeriveGateway { admin },
+            command: Commands::DeriveGateway { creator },
         };
 
         let result = run(cli);
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn parses_backend_signer_with_optional_expiry() {
+        let key = Pubkey::new_unique();
+
+        let open = parse_backend_signer(&key.to_string()).expect("plain key");
+        assert_eq!(open.signer, key);
+        assert_eq!(open.expires_at_ts, 0);
+
+        let expiring = parse_backend_signer(&format!("{key}@1700000000")).expect("key with expiry");
+        assert_eq!(expiring.signer, key);
+        assert_eq!(expiring.expires_at_ts, 1_700_000_000);
+
+        assert!(parse_backend_signer("not-a-key").is_err());
+    }
+
+    #[test]
+    fn parses_batch_entry_with_default_units() {
+        let consumer = Pubkey::new_unique();
+
+        let entry = parse_batch_entry(&format!("{consumer}:3:secret")).expect("three parts");
+        assert_eq!(entry.consumer, consumer);
+        assert_eq!(entry.api_key_id, 3);
+        assert_eq!(entry.api_key, "secret");
+        assert_eq!(entry.units, 1);
+
+        let weighted = parse_batch_entry(&format!("{consumer}:3:secret:5")).expect("four parts");
+        assert_eq!(weighted.units, 5);
+        assert_eq!(weighted.route_id, None);
+
+        let routed = parse_batch_entry(&format!("{consumer}:3:secret:5:2")).expect("five parts");
+        assert_eq!(routed.route_id, Some(2));
+
+        assert!(parse_batch_entry("missing-fields").is_err());
+    }
+
+    #[test]
+    fn parses_pricing_models() {
+        assert_eq!(
+            parse_pricing_model("linear").expect("linear"),
+            PricingModel::LinearSurge
+        );
+        assert_eq!(
+            parse_pricing_model("exp:500:1000").expect("exponential"),
+            PricingModel::Exponential {
+                growth_bps: 500,
+                step_bps: 1_000,
+            }
+        );
+
+        match parse_pricing_model("steps:5000=15000,9000=30000").expect("steps") {
+            PricingModel::StepTiers { count, tiers } => {
+                assert_eq!(count, 2);
+                assert_eq!(tiers[1].utilization_bps, 9_000);
+                assert_eq!(tiers[1].multiplier_bps, 30_000);
+            }
+            other => panic!("wrong model {other:?}"),
+        }
+
+        assert!(parse_pricing_model("piecewise:9000=1,1000=2").is_err());
+        assert!(parse_pricing_model("cubic").is_err());
+    }
+
+    #[test]
+    fn parses_volume_tier() {
+        let tier = parse_volume_tier("1000000=1000").expect("tier");
+        assert_eq!(tier.min_total_calls, 1_000_000);
+        assert_eq!(tier.discount_bps, 1_000);
+
+        assert!(parse_volume_tier("1000000").is_err());
+    }
+
     #[test]
     fn api_key_hash_is_deterministic() {
         assert_eq!(api_key_hash("abc"), api_key_hash("abc"));
@@ -19,6 +19,20 @@ pub enum GatewayError {
     ApiKeyMismatch = 6,
     #[error("already initialized")]
     AlreadyInitialized = 7,
+    #[error("gateway paused")]
+    GatewayPaused = 8,
+    #[error("consumer suspended")]
+    ConsumerSuspended = 9,
+    #[error("gateway still has registered consumers")]
+    ConsumersOutstanding = 10,
+    #[error("account layout is outdated, run MigrateAccount")]
+    AccountNeedsMigration = 11,
+    #[error("gateway capacity exhausted")]
+    GatewayOverloaded = 12,
+    #[error("consumer has outstanding debt")]
+    DebtOutstanding = 13,
+    #[error("gateway still has plans or route policies")]
+    PoliciesOutstanding = 14,
 }
 
 impl From<GatewayError> for ProgramError {
@@ -1,4 +1,32 @@
 use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::pubkey::Pubkey;
+
+use crate::{
+    pricing::{PricingModel, VolumeDiscountTier},
+    state::{BackendSigner, ConsumerOverrides, QuotaMode},
+};
+
+/// Upper bound on `ConsumeBatch` entries; keeps the per-entry outcomes within
+/// the 1 KiB return-data limit.
+pub const MAX_BATCH_ENTRIES: usize = 32;
+
+#[derive(Debug, Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq, Eq)]
+pub struct BatchConsumeEntry {
+    pub api_key_id: u64,
+    pub presented_api_key_hash: [u8; 32],
+    pub units: u64,
+    pub route_id: Option<u64>,
+}
+
+/// Per-entry result of `ConsumeBatch`, returned as borsh-encoded return data.
+#[derive(Debug, Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq, Eq)]
+pub struct BatchConsumeOutcome {
+    pub charged_lamports: u64,
+    /// `0` on success, otherwise the `ProgramError` code (custom codes are `GatewayError`).
+    pub error_code: u64,
+    /// Wait before the entry would pass the GCRA limiter; `0` unless it was rate limited by it.
+    pub retry_after_ms: u64,
+}
 
 #[derive(Debug, Clone, BorshSerialize, BorshDeserialize, PartialEq, Eq)]
 pub enum GatewayInstruction {
@@ -14,13 +42,159 @@ pub enum GatewayInstruction {
         api_key_id: u64,
         api_key_hash: [u8; 32],
     },
+    /// Native lamports, or base units of the billing mint into the consumer's
+    /// token vault (source, vault, mint and token program follow).
     TopUp {
         lamports: u64,
     },
     Consume {
         api_key_id: u64,
         presented_api_key_hash: [u8; 32],
+        /// Cost weight of the call: tokens, quota units and price multiples to charge.
+        units: u64,
+        /// Route whose `RoutePolicy` prices and throttles the call; its account
+        /// must be passed after the treasury.
+        route_id: Option<u64>,
+    },
+    UpdateGatewayConfig {
+        base_price_lamports: Option<u64>,
+        max_surge_bps: Option<u16>,
+        period_limit: Option<u64>,
+        period_seconds: Option<i64>,
+        bucket_capacity: Option<u64>,
+        refill_per_second: Option<u64>,
+    },
+    ProposeAdmin {
+        new_admin: Pubkey,
+    },
+    AcceptAdmin,
+    SetBackendSigners {
+        signers: Vec<BackendSigner>,
+    },
+    PauseGateway,
+    ResumeGateway,
+    /// Native lamports, or base units of the billing mint out of the consumer's
+    /// token vault (destination, vault, mint and token program follow).
+    Withdraw {
+        lamports: u64,
+    },
+    CloseConsumer,
+    RotateApiKey {
+        new_hash: [u8; 32],
+        grace_seconds: i64,
+    },
+    SuspendConsumer {
+        revoke: bool,
+    },
+    ReinstateConsumer,
+    CloseGateway,
+    MigrateAccount,
+    /// Charges many consumers at once; consumer accounts follow the fixed
+    /// accounts in entry order, then any route policies. A failing entry is
+    /// reported, not fatal.
+    ConsumeBatch {
+        entries: Vec<BatchConsumeEntry>,
+    },
+    /// Creates or updates the `RoutePolicy` for `route_id`. An update carries the
+    /// current counters over to the new limits.
+    SetRoutePolicy {
+        route_id: u64,
+        base_price_lamports: u64,
+        max_surge_bps: u16,
+        period_limit: u64,
+        period_seconds: i64,
+        bucket_capacity: u64,
+        refill_per_second: u64,
+    },
+    CloseRoutePolicy {
+        route_id: u64,
+    },
+    /// Creates or updates the `Plan` for `plan_id`. Assigned consumers pick up
+    /// the new rules on their next call.
+    SetPlan {
+        plan_id: u64,
+        base_price_lamports: u64,
+        max_surge_bps: u16,
+        period_limit: u64,
+        period_seconds: i64,
+        bucket_capacity: u64,
+        refill_per_second: u64,
+    },
+    ClosePlan {
+        plan_id: u64,
+    },
+    /// Moves a consumer onto `plan_id`, or back to the gateway rules with `None`.
+    /// The current plan account (if any) and the new one (if any) follow the consumer.
+    ChangePlan {
+        plan_id: Option<u64>,
+    },
+    /// Replaces the consumer's negotiated overrides; all-zero clears them.
+    SetConsumerOverrides {
+        overrides: ConsumerOverrides,
+    },
+    /// Replaces the gateway's price curve; applies to plans and routes too.
+    SetPricingModel {
+        model: PricingModel,
+    },
+    /// Replaces the lifetime volume discount tiers (at most 4, ascending by call count).
+    SetVolumeDiscounts {
+        tiers: Vec<VolumeDiscountTier>,
+    },
+    /// Sets fractional bucket refill on the gateway, or on `plan_id` (whose
+    /// account follows the gateway). `refill_milli_tokens == 0` restores
+    /// whole-token `refill_per_second`.
+    SetFractionalRefill {
+        plan_id: Option<u64>,
+        refill_milli_tokens: u64,
+        refill_interval_seconds: i64,
+    },
+    /// Selects how the period quota is counted for every consumer of the gateway.
+    SetQuotaMode {
+        mode: QuotaMode,
+    },
+    /// Switches consumers of the gateway to the GCRA limiter; an emission
+    /// interval of `0` restores the token bucket.
+    SetGcraLimiter {
+        emission_interval_ms: u64,
+        burst_tolerance_ms: u64,
+    },
+    /// Creates or updates the gateway's `GatewayStats` capacity. An update carries
+    /// the current counters over to the new limits; all-zero limits disable it.
+    SetGatewayCapacity {
+        period_limit: u64,
+        period_seconds: i64,
+        bucket_capacity: u64,
+        refill_per_second: u64,
+    },
+    /// Prices every call by gateway-wide units over the trailing `window_seconds`
+    /// relative to `target_calls`; `target_calls == 0` restores per-consumer
+    /// quota pricing. The gateway's `GatewayStats` must exist.
+    SetCongestionPricing {
+        window_seconds: i64,
+        target_calls: u64,
+    },
+    /// Sets the free allowance on the gateway, or on `plan_id` (whose account
+    /// follows the gateway). Zeros disable it.
+    SetFreeAllowance {
+        plan_id: Option<u64>,
+        free_calls_per_period: u64,
+        trial_calls: u64,
+    },
+    /// Sets the consumer's credit line; lowering it below the current debt only
+    /// blocks further credit.
+    SetCreditLimit {
+        credit_limit_lamports: u64,
+    },
+    /// Owner-signed top-up that pays the consumer's debt to the treasury first;
+    /// any surplus is added to the prepaid balance.
+    SettleDebt {
+        lamports: u64,
     },
+    /// Switches the gateway to billing in an SPL mint. Only allowed while no
+    /// consumer is registered; the treasury becomes the given token account.
+    SetBillingMint,
+    /// Creates the consumer's token vault for a gateway that bills in an SPL mint.
+    OpenTokenVault,
 }
 
 impl GatewayInstruction {
@@ -0,0 +1,68 @@
+/// Bytes taken by the discriminator and version prefix of every versioned account.
+pub const HEADER_LEN: usize = 8 + 1;
+
+/// Account types stored with an 8-byte discriminator followed by a version byte.
+///
+/// Layouts only ever grow by appending fields whose all-zero encoding is the
+/// correct default, so an older version is upgraded by zero-extending it to
+/// `LEN` and bumping the version byte.
+pub trait VersionedLayout {
+    const DISCRIMINATOR: [u8; 8];
+    const VERSION: u8;
+    const LEN: usize;
+    /// Size of the originally deployed, unversioned layout, if any. Fields were
+    /// later inserted in the middle of it, so it is re-encoded by
+    /// `upgrade_baseline` instead of being zero-extended.
+    const BASELINE_LEN: Option<usize> = None;
+
+    /// Account size for a given layout version, or `None` if it never existed.
+    fn len_for_version(version: u8) -> Option<usize>;
+
+    /// Re-encodes a baseline account at the current version.
+    fn upgrade_baseline(_data: &[u8]) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoredLayout {
+    Current,
+    Outdated,
+    Unrecognized,
+}
+
+pub fn stored_layout<T: VersionedLayout>(data: &[u8]) -> StoredLayout {
+    if data.len() >= HEADER_LEN && data[..8] == T::DISCRIMINATOR {
+        let version = data[8];
+        return match T::len_for_version(version) {
+            Some(len) if len == data.len() && version == T::VERSION => StoredLayout::Current,
+            Some(len) if len == data.len() && version < T::VERSION => StoredLayout::Outdated,
+            _ => StoredLayout::Unrecognized,
+        };
+    }
+
+    // Baseline accounts start with `is_initialized`; zeroed accounts are not worth migrating.
+    if Some(data.len()) == T::BASELINE_LEN && data.first() == Some(&1) {
+        return StoredLayout::Outdated;
+    }
+
+    StoredLayout::Unrecognized
+}
+
+/// Returns the account bytes re-encoded at the current version, or `None`
+/// when the data is not an outdated layout of `T`.
+pub fn upgrade_layout<T: VersionedLayout>(data: &[u8]) -> Option<Vec<u8>> {
+    if stored_layout::<T>(data) != StoredLayout::Outdated {
+        return None;
+    }
+
+    if Some(data.len()) == T::BASELINE_LEN && data[..8] != T::DISCRIMINATOR {
+        return T::upgrade_baseline(data);
+    }
+
+    let mut upgraded = Vec::with_capacity(T::LEN);
+    upgraded.extend_from_slice(data);
+    upgraded.resize(T::LEN, 0);
+    upgraded[8] = T::VERSION;
+    Some(upgraded)
+}
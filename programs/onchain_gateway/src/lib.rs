@@ -1,6 +1,8 @@
 pub mod error;
 pub mod instruction;
+pub mod layout;
 pub mod logic;
+pub mod pricing;
 pub mod processor;
 pub mod state;
 
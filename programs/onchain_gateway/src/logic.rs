@@ -1,3 +1,11 @@
+use crate::{
+    pricing::{
+        apply_discount, unit_price_lamports, volume_discount_bps, PricingModel, VolumeDiscountTier,
+        MAX_VOLUME_DISCOUNT_TIERS,
+    },
+    state::{ConsumerOverrides, QuotaMode},
+};
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct BucketState {
     pub capacity: u64,
@@ -6,6 +14,80 @@ pub struct BucketState {
     pub last_refill_ts: i64,
 }
 
+pub const MILLI_TOKENS_PER_TOKEN: u64 = 1_000;
+
+/// `quota_remaining` while no period limit applies, so a limit enabled later
+/// starts with a full quota once it is clamped.
+pub const UNLIMITED_QUOTA: u64 = u64::MAX;
+
+/// Initial `quota_remaining` for a consumer under `period_limit`.
+pub fn initial_quota(period_limit: u64) -> u64 {
+    if period_limit == 0 {
+        UNLIMITED_QUOTA
+    } else {
+        period_limit
+    }
+}
+
+/// Carries the units used this period over from `old_limit` to `new_limit`,
+/// so a lowered limit also counts what was already used. A limit that was
+/// disabled before starts full at `now_ts`.
+pub fn rebase_quota(
+    old_limit: u64,
+    new_limit: u64,
+    remaining: &mut u64,
+    period_start_ts: &mut i64,
+    now_ts: i64,
+) {
+    if new_limit == 0 {
+        *remaining = UNLIMITED_QUOTA;
+    } else if old_limit == 0 {
+        *remaining = new_limit;
+        *period_start_ts = now_ts;
+    } else {
+        let used = old_limit.saturating_sub((*remaining).min(old_limit));
+        *remaining = new_limit.saturating_sub(used);
+    }
+}
+
+/// Moves a consumer's quota onto `period_limit` when it was counted against
+/// another one. Consumers migrated from the first release did not record their
+/// limit; `unrecorded_limit` stands in for it.
+fn rebase_consumer_quota(
+    state: &mut ConsumerRuntimeState,
+    unrecorded_limit: u64,
+    period_limit: u64,
+    now_ts: i64,
+) {
+    let recorded = if state.quota_period_limit == 0 && state.quota_remaining != UNLIMITED_QUOTA {
+        unrecorded_limit
+    } else {
+        state.quota_period_limit
+    };
+    if recorded != period_limit {
+        rebase_quota(
+            recorded,
+            period_limit,
+            &mut state.quota_remaining,
+            &mut state.quota_period_start_ts,
+            now_ts,
+        );
+    }
+    state.quota_period_limit = period_limit;
+}
+
+/// Token bucket kept in thousandths of a token and refilled once per whole
+/// `refill_interval_seconds`, for rates that are not a whole number of tokens per second.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MilliBucketState {
+    /// Capacity in whole tokens.
+    pub capacity: u64,
+    pub milli_tokens: u64,
+    pub refill_milli_tokens: u64,
+    pub refill_interval_seconds: i64,
+    pub last_refill_ts: i64,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct QuotaState {
     pub period_seconds: i64,
@@ -14,7 +96,41 @@ pub struct QuotaState {
     pub remaining: u64,
 }
 
+/// Sliding-window counter. Windows stay on the grid started at `window_start_ts`.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlidingWindowState {
+    pub period_seconds: i64,
+    pub window_start_ts: i64,
+    pub previous_used: u64,
+    pub current_used: u64,
+}
+
+/// Generic Cell Rate Algorithm: one call is allowed every `emission_interval_ms`,
+/// and calls may run up to `burst_tolerance_ms` ahead of that schedule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GcraParams {
+    pub emission_interval_ms: u64,
+    pub burst_tolerance_ms: u64,
+}
+
+/// Per-consumer rate limiter. Only one of them is enforced on a call.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RateLimiter {
+    /// `bucket_capacity` / refill fields of the rules (the original limiter).
+    #[default]
+    TokenBucket,
+    Gcra(GcraParams),
+}
+
+/// Gateway-wide load priced instead of the consumer's own quota position:
+/// `recent_calls` out of `target_calls` is treated as the utilization.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CongestionLoad {
+    pub recent_calls: u64,
+    pub target_calls: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub struct GatewayRules {
     pub base_price_lamports: u64,
     pub max_surge_bps: u16,
@@ -22,9 +138,21 @@ pub struct GatewayRules {
     pub period_seconds: i64,
     pub bucket_capacity: u64,
     pub refill_per_second: u64,
+    pub pricing: PricingModel,
+    pub volume_discounts: [VolumeDiscountTier; MAX_VOLUME_DISCOUNT_TIERS],
+    /// Milli-tokens added per `refill_interval_seconds`; when non-zero it replaces
+    /// `refill_per_second`.
+    pub refill_milli_tokens: u64,
+    pub refill_interval_seconds: i64,
+    pub quota_mode: QuotaMode,
+    pub limiter: RateLimiter,
+    pub congestion: Option<CongestionLoad>,
+    /// Units served free each `period_seconds`, then lifetime trial units.
+    pub free_calls_per_period: u64,
+    pub trial_calls: u64,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub struct ConsumerRuntimeState {
     pub bucket_tokens: u64,
     pub bucket_last_refill_ts: i64,
@@ -32,6 +160,34 @@ pub struct ConsumerRuntimeState {
     pub quota_period_start_ts: i64,
     pub total_calls: u64,
     pub total_spent_lamports: u64,
+    /// Fraction of a token (in milli-tokens, below 1_000) held besides `bucket_tokens`.
+    pub bucket_milli_remainder: u64,
+    pub window_previous_used: u64,
+    pub window_current_used: u64,
+    pub gcra_tat_ms: i64,
+    pub free_calls_used: u64,
+    pub free_period_start_ts: i64,
+    pub trial_calls_used: u64,
+    /// Period limit `quota_remaining` was counted against; `0` when none applied.
+    pub quota_period_limit: u64,
+}
+
+/// Limits enforced on counters shared by every consumer (e.g. a route's own capacity),
+/// as opposed to the per-consumer limits in `GatewayRules`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AggregateLimits {
+    pub period_limit: u64,
+    pub period_seconds: i64,
+    pub bucket_capacity: u64,
+    pub refill_per_second: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AggregateUsage {
+    pub bucket_tokens: u64,
+    pub bucket_last_refill_ts: i64,
+    pub quota_remaining: u64,
+    pub quota_period_start_ts: i64,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -39,6 +195,33 @@ pub enum ConsumeError {
     RateLimited,
     QuotaExceeded,
     InsufficientBalance,
+    InvalidUnits,
+    ChargeOverflow,
+}
+
+/// Merges a consumer's negotiated terms into the rules it would otherwise get.
+/// Non-zero limit overrides replace the inherited value; the discount scales the
+/// base price, so surge is applied on top of the discounted price.
+pub fn apply_overrides(rules: &GatewayRules, overrides: &ConsumerOverrides) -> GatewayRules {
+    let mut merged = *rules;
+
+    if overrides.discount_bps > 0 {
+        let discount_bps = (overrides.discount_bps as u64).min(10_000);
+        merged.base_price_lamports =
+            ((rules.base_price_lamports as u128 * (10_000 - discount_bps) as u128) / 10_000) as u64;
+    }
+    if overrides.bucket_capacity > 0 {
+        merged.bucket_capacity = overrides.bucket_capacity;
+    }
+    if overrides.refill_per_second > 0 {
+        merged.refill_per_second = overrides.refill_per_second;
+        merged.refill_milli_tokens = 0;
+    }
+    if overrides.period_limit > 0 {
+        merged.period_limit = overrides.period_limit;
+    }
+
+    merged
 }
 
 pub fn refill_bucket(bucket: &mut BucketState, now_ts: i64) {
@@ -52,6 +235,24 @@ pub fn refill_bucket(bucket: &mut BucketState, now_ts: i64) {
     bucket.last_refill_ts = now_ts;
 }
 
+pub fn refill_milli_bucket(bucket: &mut MilliBucketState, now_ts: i64) {
+    let interval = bucket.refill_interval_seconds.max(1);
+    if now_ts <= bucket.last_refill_ts {
+        return;
+    }
+
+    let intervals = (now_ts - bucket.last_refill_ts) / interval;
+    if intervals == 0 {
+        return;
+    }
+
+    let refill = (intervals as u64).saturating_mul(bucket.refill_milli_tokens);
+    let capacity = bucket.capacity.saturating_mul(MILLI_TOKENS_PER_TOKEN);
+    bucket.milli_tokens = capacity.min(bucket.milli_tokens.saturating_add(refill));
+    // Keep the unfinished interval so it still counts toward the next refill.
+    bucket.last_refill_ts += intervals * interval;
+}
+
 pub fn enforce_quota_window(quota: &mut QuotaState, now_ts: i64) {
     if quota.period_seconds <= 0 {
         return;
@@ -63,6 +264,122 @@ pub fn enforce_quota_window(quota: &mut QuotaState, now_ts: i64) {
     }
 }
 
+/// Moves the window grid forward to the window containing `now_ts`. The current
+/// count becomes the previous one only when exactly one window has passed.
+pub fn advance_sliding_window(window: &mut SlidingWindowState, now_ts: i64) {
+    if window.period_seconds <= 0 || now_ts - window.window_start_ts < window.period_seconds {
+        return;
+    }
+
+    let windows = (now_ts - window.window_start_ts) / window.period_seconds;
+    window.previous_used = if windows == 1 { window.current_used } else { 0 };
+    window.current_used = 0;
+    window.window_start_ts += windows * window.period_seconds;
+}
+
+/// Usage over the trailing period: the current count plus the previous count
+/// weighted by the share of the previous window still inside it, rounded up.
+///
+/// This is an approximation that assumes the previous window's calls were evenly
+/// spread. If they were bunched at its end, a trailing period can hold up to
+/// `previous_used * elapsed / period_seconds` more calls than the estimate, so
+/// it suits load measurement but not limits; see [`sliding_window_upper_bound`].
+pub fn sliding_window_estimate(window: &SlidingWindowState, now_ts: i64) -> u64 {
+    if window.period_seconds <= 0 {
+        return window.current_used;
+    }
+
+    let period = window.period_seconds as u128;
+    let elapsed = (now_ts - window.window_start_ts).clamp(0, window.period_seconds) as u128;
+    let weighted_previous = (window.previous_used as u128 * (period - elapsed)).div_ceil(period);
+    (weighted_previous as u64).saturating_add(window.current_used)
+}
+
+/// Most usage any trailing period ending at `now_ts` can hold: the previous
+/// window may have bunched its calls at its end, so it counts in full until the
+/// grid moves past it.
+pub fn sliding_window_upper_bound(window: &SlidingWindowState) -> u64 {
+    window.previous_used.saturating_add(window.current_used)
+}
+
+/// Admits `units` calls at `now_ms` against the theoretical arrival time `tat_ms`,
+/// returning the new TAT, or on rejection the exact wait in milliseconds before
+/// the same call would be admitted.
+pub fn gcra_admit(params: &GcraParams, tat_ms: i64, units: u64, now_ms: i64) -> Result<i64, u64> {
+    let start = i128::from(tat_ms.max(now_ms));
+    let Some(new_tat) = i128::from(units)
+        .checked_mul(i128::from(params.emission_interval_ms))
+        .and_then(|cost| start.checked_add(cost))
+    else {
+        return Err(u64::MAX);
+    };
+    let allowed_ahead =
+        i128::from(params.burst_tolerance_ms) + i128::from(params.emission_interval_ms);
+
+    let ahead = new_tat - i128::from(now_ms);
+    if ahead > allowed_ahead {
+        return Err((ahead - allowed_ahead).min(u64::MAX as i128) as u64);
+    }
+    Ok(new_tat as i64)
+}
+
+/// Wait before `units` calls would pass the GCRA limiter; `None` when the rules
+/// use the token bucket or the call is admitted now.
+pub fn gcra_retry_after_ms(
+    rules: &GatewayRules,
+    state: &ConsumerRuntimeState,
+    units: u64,
+    now_ts: i64,
+) -> Option<u64> {
+    match rules.limiter {
+        RateLimiter::Gcra(params) => gcra_admit(
+            &params,
+            state.gcra_tat_ms,
+            units,
+            now_ts.saturating_mul(1_000),
+        )
+        .err(),
+        RateLimiter::TokenBucket => None,
+    }
+}
+
+/// Adds `units` to a gateway-wide load window and returns the load over the
+/// trailing window, this call included.
+pub fn record_congestion(window: &mut SlidingWindowState, units: u64, now_ts: i64) -> u64 {
+    advance_sliding_window(window, now_ts);
+    window.current_used = window.current_used.saturating_add(units);
+    sliding_window_estimate(window, now_ts)
+}
+
+/// Covers as many of `units` as the free allowance allows, per-period units
+/// first and then trial units, and returns how many were covered. The period
+/// restarts at `now_ts` once `period_seconds` have passed.
+pub fn take_free_units(
+    rules: &GatewayRules,
+    state: &mut ConsumerRuntimeState,
+    units: u64,
+    now_ts: i64,
+) -> u64 {
+    if rules.period_seconds > 0 && now_ts - state.free_period_start_ts >= rules.period_seconds {
+        state.free_calls_used = 0;
+        state.free_period_start_ts = now_ts;
+    }
+
+    let period_free = rules
+        .free_calls_per_period
+        .saturating_sub(state.free_calls_used)
+        .min(units);
+    state.free_calls_used += period_free;
+
+    let trial_free = rules
+        .trial_calls
+        .saturating_sub(state.trial_calls_used)
+        .min(units - period_free);
+    state.trial_calls_used += trial_free;
+
+    period_free + trial_free
+}
+
 pub fn dynamic_price_lamports(
     base_price_lamports: u64,
     period_limit: u64,
@@ -88,6 +405,23 @@ pub fn can_charge(available_balance: u64, minimum_rent: u64, charge_lamports: u6
     }
 }
 
+/// Splits `charge` into the part paid now from the balance above `minimum_rent`
+/// and the resulting debt. `None` when the debt would pass `credit_limit_lamports`.
+pub fn split_charge(
+    available_balance: u64,
+    minimum_rent: u64,
+    charge_lamports: u64,
+    debt_lamports: u64,
+    credit_limit_lamports: u64,
+) -> Option<(u64, u64)> {
+    let paid = charge_lamports.min(available_balance.saturating_sub(minimum_rent));
+    let debt = debt_lamports.checked_add(charge_lamports - paid)?;
+    if paid < charge_lamports && debt > credit_limit_lamports {
+        return None;
+    }
+    Some((paid, debt))
+}
+
 pub fn apply_consume(
     rules: &GatewayRules,
     state: &mut ConsumerRuntimeState,
@@ -95,9 +429,58 @@ pub fn apply_consume(
     available_balance: u64,
     minimum_rent: u64,
 ) -> Result<u64, ConsumeError> {
+    apply_consume_units(rules, state, 1, now_ts, available_balance, minimum_rent)
+}
+
+/// Weighted consume: burns `units` bucket tokens and quota units and charges
+/// `units` times the unit price. State is only updated when the whole call succeeds.
+pub fn apply_consume_units(
+    rules: &GatewayRules,
+    state: &mut ConsumerRuntimeState,
+    units: u64,
+    now_ts: i64,
+    available_balance: u64,
+    minimum_rent: u64,
+) -> Result<u64, ConsumeError> {
+    if units == 0 {
+        return Err(ConsumeError::InvalidUnits);
+    }
+
     let mut next_state = *state;
 
-    if rules.bucket_capacity > 0 {
+    if let RateLimiter::Gcra(params) = rules.limiter {
+        next_state.gcra_tat_ms = gcra_admit(
+            &params,
+            next_state.gcra_tat_ms,
+            units,
+            now_ts.saturating_mul(1_000),
+        )
+        .map_err(|_| ConsumeError::RateLimited)?;
+    } else if rules.bucket_capacity > 0 && rules.refill_milli_tokens > 0 {
+        let mut bucket = MilliBucketState {
+            capacity: rules.bucket_capacity,
+            milli_tokens: next_state
+                .bucket_tokens
+                .saturating_mul(MILLI_TOKENS_PER_TOKEN)
+                .saturating_add(next_state.bucket_milli_remainder),
+            refill_milli_tokens: rules.refill_milli_tokens,
+            refill_interval_seconds: rules.refill_interval_seconds,
+            last_refill_ts: next_state.bucket_last_refill_ts,
+        };
+        refill_milli_bucket(&mut bucket, now_ts);
+
+        let cost = units
+            .checked_mul(MILLI_TOKENS_PER_TOKEN)
+            .ok_or(ConsumeError::RateLimited)?;
+        if bucket.milli_tokens < cost {
+            return Err(ConsumeError::RateLimited);
+        }
+
+        bucket.milli_tokens -= cost;
+        next_state.bucket_tokens = bucket.milli_tokens / MILLI_TOKENS_PER_TOKEN;
+        next_state.bucket_milli_remainder = bucket.milli_tokens % MILLI_TOKENS_PER_TOKEN;
+        next_state.bucket_last_refill_ts = bucket.last_refill_ts;
+    } else if rules.bucket_capacity > 0 {
         let mut bucket = BucketState {
             capacity: rules.bucket_capacity,
             tokens: next_state.bucket_tokens,
@@ -106,41 +489,90 @@ pub fn apply_consume(
         };
         refill_bucket(&mut bucket, now_ts);
 
-        if bucket.tokens == 0 {
+        if bucket.tokens < units {
             return Err(ConsumeError::RateLimited);
         }
 
-        bucket.tokens = bucket.tokens.saturating_sub(1);
+        bucket.tokens -= units;
         next_state.bucket_tokens = bucket.tokens;
         next_state.bucket_last_refill_ts = bucket.last_refill_ts;
     }
 
+    // The limit may have changed since the counters were written (gateway
+    // update, override or plan change); units already used still count.
+    rebase_consumer_quota(
+        &mut next_state,
+        rules.period_limit,
+        rules.period_limit,
+        now_ts,
+    );
     let mut remaining_quota_for_price = next_state.quota_remaining;
 
-    if rules.period_limit > 0 {
+    if rules.period_limit > 0 && rules.quota_mode == QuotaMode::SlidingWindow {
+        let mut window = SlidingWindowState {
+            period_seconds: rules.period_seconds,
+            window_start_ts: next_state.quota_period_start_ts,
+            previous_used: next_state.window_previous_used,
+            current_used: next_state.window_current_used,
+        };
+        advance_sliding_window(&mut window, now_ts);
+
+        let used = sliding_window_upper_bound(&window)
+            .checked_add(units)
+            .ok_or(ConsumeError::QuotaExceeded)?;
+        if used > rules.period_limit {
+            return Err(ConsumeError::QuotaExceeded);
+        }
+
+        window.current_used += units;
+        remaining_quota_for_price = rules.period_limit - used;
+        next_state.quota_remaining = remaining_quota_for_price;
+        next_state.quota_period_start_ts = window.window_start_ts;
+        next_state.window_previous_used = window.previous_used;
+        next_state.window_current_used = window.current_used;
+    } else if rules.period_limit > 0 {
+        // An unrecorded limit leaves at most the current one.
         let mut quota = QuotaState {
             period_seconds: rules.period_seconds,
             period_start_ts: next_state.quota_period_start_ts,
             period_limit: rules.period_limit,
-            remaining: next_state.quota_remaining,
+            remaining: next_state.quota_remaining.min(rules.period_limit),
         };
 
         enforce_quota_window(&mut quota, now_ts);
-        if quota.remaining == 0 {
+        if quota.remaining < units {
             return Err(ConsumeError::QuotaExceeded);
         }
 
-        quota.remaining = quota.remaining.saturating_sub(1);
+        quota.remaining -= units;
         remaining_quota_for_price = quota.remaining;
         next_state.quota_remaining = quota.remaining;
         next_state.quota_period_start_ts = quota.period_start_ts;
+    } else {
+        next_state.quota_remaining = UNLIMITED_QUOTA;
     }
 
-    let price = dynamic_price_lamports(
+    let (price_limit, price_remaining) = match rules.congestion {
+        Some(load) => (
+            load.target_calls,
+            load.target_calls.saturating_sub(load.recent_calls),
+        ),
+        None => (rules.period_limit, remaining_quota_for_price),
+    };
+    let unit_price = unit_price_lamports(
+        &rules.pricing,
         rules.base_price_lamports,
-        rules.period_limit,
-        remaining_quota_for_price,
         rules.max_surge_bps,
+        price_limit,
+        price_remaining,
+    );
+    let free_units = take_free_units(rules, &mut next_state, units, now_ts);
+    let surged_price = unit_price
+        .checked_mul(units - free_units)
+        .ok_or(ConsumeError::ChargeOverflow)?;
+    let price = apply_discount(
+        surged_price,
+        volume_discount_bps(&rules.volume_discounts, next_state.total_calls),
     );
 
     if !can_charge(available_balance, minimum_rent, price) {
@@ -153,3 +585,101 @@ pub fn apply_consume(
 
     Ok(price)
 }
+
+/// Burns `units` from shared counters. `usage` is only updated when both the
+/// bucket and the period quota admit the call; a zero capacity or limit disables that check.
+pub fn apply_aggregate_limits(
+    limits: &AggregateLimits,
+    usage: &mut AggregateUsage,
+    units: u64,
+    now_ts: i64,
+) -> Result<(), ConsumeError> {
+    let mut next_usage = *usage;
+
+    if limits.bucket_capacity > 0 {
+        let mut bucket = BucketState {
+            capacity: limits.bucket_capacity,
+            tokens: next_usage.bucket_tokens,
+            refill_per_second: limits.refill_per_second,
+            last_refill_ts: next_usage.bucket_last_refill_ts,
+        };
+        refill_bucket(&mut bucket, now_ts);
+
+        if bucket.tokens < units {
+            return Err(ConsumeError::RateLimited);
+        }
+
+        next_usage.bucket_tokens = bucket.tokens - units;
+        next_usage.bucket_last_refill_ts = bucket.last_refill_ts;
+    }
+
+    if limits.period_limit > 0 {
+        let mut quota = QuotaState {
+            period_seconds: limits.period_seconds,
+            period_start_ts: next_usage.quota_period_start_ts,
+            period_limit: limits.period_limit,
+            remaining: next_usage.quota_remaining,
+        };
+
+        enforce_quota_window(&mut quota, now_ts);
+        if quota.remaining < units {
+            return Err(ConsumeError::QuotaExceeded);
+        }
+
+        next_usage.quota_remaining = quota.remaining - units;
+        next_usage.quota_period_start_ts = quota.period_start_ts;
+    }
+
+    *usage = next_usage;
+    Ok(())
+}
+
+/// Carries shared counters over when their limits change: units already used
+/// this period still count, banked tokens are capped at the new capacity, and a
+/// bucket or limit that was disabled before starts full at `now_ts`.
+pub fn rebase_aggregate_usage(
+    old_limits: &AggregateLimits,
+    new_limits: &AggregateLimits,
+    usage: &mut AggregateUsage,
+    now_ts: i64,
+) {
+    if old_limits.bucket_capacity == 0 {
+        usage.bucket_tokens = new_limits.bucket_capacity;
+        usage.bucket_last_refill_ts = now_ts;
+    } else {
+        usage.bucket_tokens = usage.bucket_tokens.min(new_limits.bucket_capacity);
+    }
+
+    rebase_quota(
+        old_limits.period_limit,
+        new_limits.period_limit,
+        &mut usage.quota_remaining,
+        &mut usage.quota_period_start_ts,
+        now_ts,
+    );
+}
+
+/// Carries a consumer's counters over when its rules change (e.g. a plan switch).
+/// Units already used this period still count against the new quota, and banked
+/// tokens are capped at the new capacity. A limit that was disabled before starts
+/// fresh at `now_ts`.
+pub fn rebase_runtime_state(
+    old_rules: &GatewayRules,
+    new_rules: &GatewayRules,
+    state: &mut ConsumerRuntimeState,
+    now_ts: i64,
+) {
+    if old_rules.bucket_capacity == 0 {
+        state.bucket_tokens = new_rules.bucket_capacity;
+        state.bucket_last_refill_ts = now_ts;
+    } else {
+        state.bucket_tokens = state.bucket_tokens.min(new_rules.bucket_capacity);
+    }
+
+    rebase_consumer_quota(
+        state,
+        old_rules.period_limit,
+        new_rules.period_limit,
+        now_ts,
+    );
+}
@@ -0,0 +1,274 @@
+use std::io::{Read, Write};
+
+use borsh::{BorshDeserialize, BorshSerialize};
+
+use crate::logic::dynamic_price_lamports;
+
+pub const MAX_PRICE_POINTS: usize = 4;
+pub const MAX_VOLUME_DISCOUNT_TIERS: usize = 4;
+
+/// Utilization steps evaluated by `Exponential` are at least this wide, which
+/// bounds the compounding loop to 100 iterations.
+pub const MIN_EXPONENTIAL_STEP_BPS: u16 = 100;
+
+/// Point of a price schedule: at `utilization_bps` of the period quota the unit
+/// price is `multiplier_bps / 10_000` times the base price.
+#[derive(Debug, Clone, Copy, Default, BorshSerialize, BorshDeserialize, PartialEq, Eq)]
+pub struct PricePoint {
+    pub utilization_bps: u16,
+    pub multiplier_bps: u32,
+}
+
+impl PricePoint {
+    pub const LEN: usize = 2 + 4;
+}
+
+/// Lifetime discount: once a consumer has made `min_total_calls` calls, its
+/// charges are reduced by `discount_bps`. A slot with `discount_bps == 0` is unused.
+#[derive(Debug, Clone, Copy, Default, BorshSerialize, BorshDeserialize, PartialEq, Eq)]
+pub struct VolumeDiscountTier {
+    pub min_total_calls: u64,
+    pub discount_bps: u16,
+}
+
+impl VolumeDiscountTier {
+    pub const LEN: usize = 8 + 2;
+
+    pub fn is_empty(&self) -> bool {
+        self.discount_bps == 0
+    }
+}
+
+/// Checks that tiers are set, ascending by call count and discount at most 100%.
+pub fn volume_tiers_are_valid(tiers: &[VolumeDiscountTier]) -> bool {
+    tiers.len() <= MAX_VOLUME_DISCOUNT_TIERS
+        && tiers
+            .iter()
+            .all(|tier| !tier.is_empty() && tier.discount_bps <= 10_000)
+        && tiers
+            .windows(2)
+            .all(|pair| pair[0].min_total_calls < pair[1].min_total_calls)
+}
+
+/// Discount of the highest tier reached by `total_calls`, in basis points.
+pub fn volume_discount_bps(tiers: &[VolumeDiscountTier], total_calls: u64) -> u16 {
+    tiers
+        .iter()
+        .filter(|tier| !tier.is_empty() && tier.min_total_calls <= total_calls)
+        .max_by_key(|tier| tier.min_total_calls)
+        .map_or(0, |tier| tier.discount_bps.min(10_000))
+}
+
+/// Reduces `price` by `discount_bps`, rounding down.
+pub fn apply_discount(price: u64, discount_bps: u16) -> u64 {
+    let discount_bps = discount_bps.min(10_000) as u128;
+    (price as u128 * (10_000 - discount_bps) / 10_000) as u64
+}
+
+/// Price curve applied to the base price as the period quota is used up.
+///
+/// Encoded as a one-byte tag followed by a fixed-size payload so accounts that
+/// store it keep a constant length whatever the variant.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PricingModel {
+    /// Linear rise up to `max_surge_bps` at full utilization (the original curve).
+    #[default]
+    LinearSurge,
+    /// Always the base price.
+    Flat,
+    /// The multiplier compounds by `growth_bps` for every `step_bps` of utilization.
+    Exponential { growth_bps: u32, step_bps: u16 },
+    /// Multiplier of the highest band whose threshold has been reached; the base
+    /// price applies below the first band.
+    StepTiers {
+        count: u8,
+        tiers: [PricePoint; MAX_PRICE_POINTS],
+    },
+    /// Linear interpolation between points, flat beyond the first and last one.
+    PiecewiseLinear {
+        count: u8,
+        points: [PricePoint; MAX_PRICE_POINTS],
+    },
+}
+
+impl PricingModel {
+    const PAYLOAD_LEN: usize = 1 + PricePoint::LEN * MAX_PRICE_POINTS;
+    pub const LEN: usize = 1 + Self::PAYLOAD_LEN;
+
+    /// Checks that schedule points are in range and strictly ascending.
+    pub fn is_valid(&self) -> bool {
+        match self {
+            PricingModel::LinearSurge | PricingModel::Flat => true,
+            PricingModel::Exponential { step_bps, .. } => *step_bps >= MIN_EXPONENTIAL_STEP_BPS,
+            PricingModel::StepTiers {
+                count,
+                tiers: points,
+            }
+            | PricingModel::PiecewiseLinear { count, points } => {
+                let count = *count as usize;
+                count > 0
+                    && count <= MAX_PRICE_POINTS
+                    && points[..count]
+                        .iter()
+                        .all(|point| point.utilization_bps <= 10_000)
+                    && points[..count]
+                        .windows(2)
+                        .all(|pair| pair[0].utilization_bps < pair[1].utilization_bps)
+            }
+        }
+    }
+}
+
+impl BorshSerialize for PricingModel {
+    fn serialize<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let mut payload = Vec::with_capacity(Self::PAYLOAD_LEN);
+        let tag: u8 = match self {
+            PricingModel::LinearSurge => 0,
+            PricingModel::Flat => 1,
+            PricingModel::Exponential {
+                growth_bps,
+                step_bps,
+            } => {
+                growth_bps.serialize(&mut payload)?;
+                step_bps.serialize(&mut payload)?;
+                2
+            }
+            PricingModel::StepTiers { count, tiers } => {
+                count.serialize(&mut payload)?;
+                tiers.serialize(&mut payload)?;
+                3
+            }
+            PricingModel::PiecewiseLinear { count, points } => {
+                count.serialize(&mut payload)?;
+                points.serialize(&mut payload)?;
+                4
+            }
+        };
+        payload.resize(Self::PAYLOAD_LEN, 0);
+
+        writer.write_all(&[tag])?;
+        writer.write_all(&payload)
+    }
+}
+
+impl BorshDeserialize for PricingModel {
+    fn deserialize_reader<R: Read>(reader: &mut R) -> std::io::Result<Self> {
+        let tag = u8::deserialize_reader(reader)?;
+        let mut payload = [0u8; Self::PAYLOAD_LEN];
+        reader.read_exact(&mut payload)?;
+        let payload = &mut payload.as_slice();
+
+        match tag {
+            0 => Ok(PricingModel::LinearSurge),
+            1 => Ok(PricingModel::Flat),
+            2 => Ok(PricingModel::Exponential {
+                growth_bps: u32::deserialize(payload)?,
+                step_bps: u16::deserialize(payload)?,
+            }),
+            3 => Ok(PricingModel::StepTiers {
+                count: u8::deserialize(payload)?,
+                tiers: <[PricePoint; MAX_PRICE_POINTS]>::deserialize(payload)?,
+            }),
+            4 => Ok(PricingModel::PiecewiseLinear {
+                count: u8::deserialize(payload)?,
+                points: <[PricePoint; MAX_PRICE_POINTS]>::deserialize(payload)?,
+            }),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "unknown pricing model",
+            )),
+        }
+    }
+}
+
+/// Share of the period quota already used, in basis points. `0` when there is no quota.
+pub fn utilization_bps(period_limit: u64, remaining_quota: u64) -> u64 {
+    if period_limit == 0 {
+        return 0;
+    }
+
+    let used = period_limit.saturating_sub(remaining_quota.min(period_limit));
+    ((used as u128 * 10_000) / period_limit as u128) as u64
+}
+
+/// Unit price for the given quota position under `model`.
+pub fn unit_price_lamports(
+    model: &PricingModel,
+    base_price_lamports: u64,
+    max_surge_bps: u16,
+    period_limit: u64,
+    remaining_quota: u64,
+) -> u64 {
+    let utilization = utilization_bps(period_limit, remaining_quota);
+    let multiplier_bps = match model {
+        PricingModel::LinearSurge => {
+            return dynamic_price_lamports(
+                base_price_lamports,
+                period_limit,
+                remaining_quota,
+                max_surge_bps,
+            );
+        }
+        PricingModel::Flat => 10_000,
+        PricingModel::Exponential {
+            growth_bps,
+            step_bps,
+        } => exponential_multiplier_bps(*growth_bps, *step_bps, utilization),
+        PricingModel::StepTiers { count, tiers } => tiers
+            [..(*count as usize).min(MAX_PRICE_POINTS)]
+            .iter()
+            .take_while(|tier| tier.utilization_bps as u64 <= utilization)
+            .last()
+            .map_or(10_000, |tier| tier.multiplier_bps as u64),
+        PricingModel::PiecewiseLinear { count, points } => piecewise_multiplier_bps(
+            &points[..(*count as usize).min(MAX_PRICE_POINTS)],
+            utilization,
+        ),
+    };
+
+    apply_multiplier(base_price_lamports, multiplier_bps)
+}
+
+fn apply_multiplier(base_price_lamports: u64, multiplier_bps: u64) -> u64 {
+    let price = base_price_lamports as u128 * multiplier_bps as u128 / 10_000;
+    price.min(u64::MAX as u128) as u64
+}
+
+fn exponential_multiplier_bps(growth_bps: u32, step_bps: u16, utilization: u64) -> u64 {
+    let steps = utilization / step_bps.max(MIN_EXPONENTIAL_STEP_BPS) as u64;
+    let factor_bps = 10_000 + growth_bps as u128;
+
+    let mut multiplier_bps: u128 = 10_000;
+    for _ in 0..steps {
+        multiplier_bps = multiplier_bps * factor_bps / 10_000;
+        if multiplier_bps >= u64::MAX as u128 {
+            return u64::MAX;
+        }
+    }
+    multiplier_bps as u64
+}
+
+fn piecewise_multiplier_bps(points: &[PricePoint], utilization: u64) -> u64 {
+    let (Some(first), Some(last)) = (points.first(), points.last()) else {
+        return 10_000;
+    };
+    if utilization <= first.utilization_bps as u64 {
+        return first.multiplier_bps as u64;
+    }
+    if utilization >= last.utilization_bps as u64 {
+        return last.multiplier_bps as u64;
+    }
+
+    for pair in points.windows(2) {
+        let (from, to) = (pair[0], pair[1]);
+        let (x0, x1) = (from.utilization_bps as u64, to.utilization_bps as u64);
+        if utilization > x1 || x1 <= x0 {
+            continue;
+        }
+
+        let (y0, y1) = (from.multiplier_bps as i128, to.multiplier_bps as i128);
+        let offset = (y1 - y0) * (utilization - x0) as i128 / (x1 - x0) as i128;
+        return (y0 + offset) as u64;
+    }
+    last.multiplier_bps as u64
+}
@@ -6,19 +6,39 @@ use solana_program::{
     clock::Clock,
     entrypoint::ProgramResult,
     msg,
-    program::{invoke, invoke_signed},
+    program::{invoke, invoke_signed, set_return_data},
     program_error::ProgramError,
     pubkey::Pubkey,
     rent::Rent,
     system_instruction, system_program,
     sysvar::Sysvar,
 };
+use spl_token_2022::{
+    check_spl_token_program_account,
+    extension::{BaseStateWithExtensions, ExtensionType, StateWithExtensions},
+    state::{Account as TokenAccount, Mint},
+};
 
 use crate::{
     error::GatewayError,
-    instruction::GatewayInstruction,
-    logic::{apply_consume, ConsumeError, ConsumerRuntimeState, GatewayRules},
-    state::{consumer_pda, gateway_pda, ConsumerAccount, GatewayConfig},
+    instruction::{BatchConsumeEntry, BatchConsumeOutcome, GatewayInstruction, MAX_BATCH_ENTRIES},
+    layout::{stored_layout, upgrade_layout, StoredLayout, VersionedLayout},
+    logic::{
+        apply_aggregate_limits, apply_consume_units, apply_overrides, can_charge,
+        gcra_retry_after_ms, initial_quota, rebase_aggregate_usage, rebase_runtime_state,
+        record_congestion, split_charge, AggregateLimits, AggregateUsage, CongestionLoad,
+        ConsumeError, ConsumerRuntimeState, GatewayRules, GcraParams, RateLimiter,
+        SlidingWindowState,
+    },
+    pricing::{
+        volume_tiers_are_valid, PricingModel, VolumeDiscountTier, MAX_VOLUME_DISCOUNT_TIERS,
+    },
+    state::{
+        consumer_pda, consumer_vault_pda, gateway_pda, gateway_stats_pda, mint_transfer_fee,
+        plan_pda, route_policy_pda, withheld_transfer_fees, BackendSigner, ConsumerAccount,
+        ConsumerOverrides, ConsumerStatus, GatewayConfig, GatewayStats, Plan, QuotaMode,
+        RoutePolicy, MAX_BACKEND_SIGNERS,
+    },
 };
 
 pub fn process_instruction(
@@ -51,11 +71,192 @@ pub fn process_instruction(
             api_key_id,
             api_key_hash,
         } => process_register_consumer(program_id, accounts, api_key_id, api_key_hash),
-        GatewayInstruction::TopUp { lamports } => process_topup(accounts, lamports),
+        GatewayInstruction::TopUp { lamports } => process_topup(program_id, accounts, lamports),
         GatewayInstruction::Consume {
             api_key_id,
             presented_api_key_hash,
-        } => process_consume(accounts, api_key_id, presented_api_key_hash),
+            units,
+            route_id,
+        } => process_consume(
+            program_id,
+            accounts,
+            api_key_id,
+            presented_api_key_hash,
+            units,
+            route_id,
+        ),
+        GatewayInstruction::UpdateGatewayConfig {
+            base_price_lamports,
+            max_surge_bps,
+            period_limit,
+            period_seconds,
+            bucket_capacity,
+            refill_per_second,
+        } => process_update_gateway_config(
+            program_id,
+            accounts,
+            base_price_lamports,
+            max_surge_bps,
+            period_limit,
+            period_seconds,
+            bucket_capacity,
+            refill_per_second,
+        ),
+        GatewayInstruction::ProposeAdmin { new_admin } => {
+            process_propose_admin(program_id, accounts, new_admin)
+        }
+        GatewayInstruction::AcceptAdmin => process_accept_admin(program_id, accounts),
+        GatewayInstruction::SetBackendSigners { signers } => {
+            process_set_backend_signers(program_id, accounts, signers)
+        }
+        GatewayInstruction::PauseGateway => process_set_paused(program_id, accounts, true),
+        GatewayInstruction::ResumeGateway => process_set_paused(program_id, accounts, false),
+        GatewayInstruction::Withdraw { lamports } => {
+            process_withdraw(program_id, accounts, lamports)
+        }
+        GatewayInstruction::CloseConsumer => process_close_consumer(program_id, accounts),
+        GatewayInstruction::RotateApiKey {
+            new_hash,
+            grace_seconds,
+        } => process_rotate_api_key(program_id, accounts, new_hash, grace_seconds),
+        GatewayInstruction::SuspendConsumer { revoke } => {
+            let status = if revoke {
+                ConsumerStatus::Revoked
+            } else {
+                ConsumerStatus::Suspended
+            };
+            process_set_consumer_status(program_id, accounts, status)
+        }
+        GatewayInstruction::ReinstateConsumer => {
+            process_set_consumer_status(program_id, accounts, ConsumerStatus::Active)
+        }
+        GatewayInstruction::CloseGateway => process_close_gateway(program_id, accounts),
+        GatewayInstruction::MigrateAccount => process_migrate_account(program_id, accounts),
+        GatewayInstruction::ConsumeBatch { entries } => {
+            process_consume_batch(program_id, accounts, entries)
+        }
+        GatewayInstruction::SetRoutePolicy {
+            route_id,
+            base_price_lamports,
+            max_surge_bps,
+            period_limit,
+            period_seconds,
+            bucket_capacity,
+            refill_per_second,
+        } => process_set_route_policy(
+            program_id,
+            accounts,
+            route_id,
+            base_price_lamports,
+            max_surge_bps,
+            AggregateLimits {
+                period_limit,
+                period_seconds,
+                bucket_capacity,
+                refill_per_second,
+            },
+        ),
+        GatewayInstruction::CloseRoutePolicy { route_id } => {
+            process_close_route_policy(program_id, accounts, route_id)
+        }
+        GatewayInstruction::SetPlan {
+            plan_id,
+            base_price_lamports,
+            max_surge_bps,
+            period_limit,
+            period_seconds,
+            bucket_capacity,
+            refill_per_second,
+        } => process_set_plan(
+            program_id,
+            accounts,
+            plan_id,
+            GatewayRules {
+                base_price_lamports,
+                max_surge_bps,
+                period_limit,
+                period_seconds,
+                bucket_capacity,
+                refill_per_second,
+                ..GatewayRules::default()
+            },
+        ),
+        GatewayInstruction::ClosePlan { plan_id } => {
+            process_close_plan(program_id, accounts, plan_id)
+        }
+        GatewayInstruction::ChangePlan { plan_id } => {
+            process_change_plan(program_id, accounts, plan_id)
+        }
+        GatewayInstruction::SetConsumerOverrides { overrides } => {
+            process_set_consumer_overrides(program_id, accounts, overrides)
+        }
+        GatewayInstruction::SetPricingModel { model } => {
+            process_set_pricing_model(program_id, accounts, model)
+        }
+        GatewayInstruction::SetVolumeDiscounts { tiers } => {
+            process_set_volume_discounts(program_id, accounts, tiers)
+        }
+        GatewayInstruction::SetFractionalRefill {
+            plan_id,
+            refill_milli_tokens,
+            refill_interval_seconds,
+        } => process_set_fractional_refill(
+            program_id,
+            accounts,
+            plan_id,
+            refill_milli_tokens,
+            refill_interval_seconds,
+        ),
+        GatewayInstruction::SetQuotaMode { mode } => {
+            process_set_quota_mode(program_id, accounts, mode)
+        }
+        GatewayInstruction::SetGatewayCapacity {
+            period_limit,
+            period_seconds,
+            bucket_capacity,
+            refill_per_second,
+        } => process_set_gateway_capacity(
+            program_id,
+            accounts,
+            AggregateLimits {
+                period_limit,
+                period_seconds,
+                bucket_capacity,
+                refill_per_second,
+            },
+        ),
+        GatewayInstruction::SetBillingMint => process_set_billing_mint(program_id, accounts),
+        GatewayInstruction::OpenTokenVault => process_open_token_vault(program_id, accounts),
+        GatewayInstruction::SetCreditLimit {
+            credit_limit_lamports,
+        } => process_set_credit_limit(program_id, accounts, credit_limit_lamports),
+        GatewayInstruction::SettleDebt { lamports } => {
+            process_settle_debt(program_id, accounts, lamports)
+        }
+        GatewayInstruction::SetFreeAllowance {
+            plan_id,
+            free_calls_per_period,
+            trial_calls,
+        } => process_set_free_allowance(
+            program_id,
+            accounts,
+            plan_id,
+            free_calls_per_period,
+            trial_calls,
+        ),
+        GatewayInstruction::SetCongestionPricing {
+            window_seconds,
+            target_calls,
+        } => process_set_congestion_pricing(program_id, accounts, window_seconds, target_calls),
+        GatewayInstruction::SetGcraLimiter {
+            emission_interval_ms,
+            burst_tolerance_ms,
+        } => process_set_gcra_limiter(
+            program_id,
+            accounts,
+            emission_interval_ms,
+            burst_tolerance_ms,
+        ),
     }
 }
 
@@ -103,18 +304,44 @@ fn process_initialize_gateway(
         return Err(GatewayError::AlreadyInitialized.into());
     }
 
+    let mut backend_signers = [BackendSigner::default(); MAX_BACKEND_SIGNERS];
+    backend_signers[0] = BackendSigner {
+        signer: *backend_signer.key,
+        expires_at_ts: 0,
+    };
+
     let cfg = GatewayConfig {
+        discriminator: GatewayConfig::DISCRIMINATOR,
+        version: GatewayConfig::VERSION,
         is_initialized: true,
         admin: *admin.key,
+        pending_admin: Pubkey::default(),
+        creator: *admin.key,
         treasury: *treasury.key,
-        backend_signer: *backend_signer.key,
+        backend_signers,
         base_price_lamports,
         max_surge_bps,
         period_limit,
         period_seconds,
         bucket_capacity,
         refill_per_second,
+        paused: false,
+        consumer_count: 0,
         bump,
+        pricing: PricingModel::default(),
+        volume_discounts: [VolumeDiscountTier::default(); MAX_VOLUME_DISCOUNT_TIERS],
+        refill_milli_tokens: 0,
+        refill_interval_seconds: 0,
+        quota_mode: QuotaMode::default(),
+        gcra_emission_interval_ms: 0,
+        gcra_burst_tolerance_ms: 0,
+        stats: Pubkey::default(),
+        free_calls_per_period: 0,
+        trial_calls: 0,
+        billing_mint: Pubkey::default(),
+        billing_decimals: 0,
+        plan_count: 0,
+        route_count: 0,
     };
 
     write_gateway(gateway_account, &cfg)?;
@@ -122,6 +349,50 @@ fn process_initialize_gateway(
     Ok(())
 }
 
+fn process_close_gateway(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let mut iter = accounts.iter();
+    let admin = next_account_info(&mut iter)?;
+    let gateway_account = next_account_info(&mut iter)?;
+
+    require_signer(admin)?;
+    require_writable(admin)?;
+    require_writable(gateway_account)?;
+
+    let cfg = read_admin_gateway(program_id, admin, gateway_account)?;
+    if cfg.consumer_count > 0 {
+        msg!("{} consumers still registered", cfg.consumer_count);
+        return Err(GatewayError::ConsumersOutstanding.into());
+    }
+    // Plans and routes can only be closed through a live gateway.
+    if cfg.plan_count > 0 || cfg.route_count > 0 {
+        msg!(
+            "{} plans and {} route policies still open",
+            cfg.plan_count,
+            cfg.route_count
+        );
+        return Err(GatewayError::PoliciesOutstanding.into());
+    }
+
+    if cfg.has_stats() {
+        let stats_account = next_account_info(&mut iter)?;
+        require_writable(stats_account)?;
+        if *stats_account.key != cfg.stats || stats_account.owner != program_id {
+            return Err(GatewayError::InvalidAccount.into());
+        }
+
+        let balance = **stats_account.lamports.borrow();
+        move_lamports(stats_account, admin, balance)?;
+        stats_account.try_borrow_mut_data()?.fill(0);
+    }
+
+    let balance = **gateway_account.lamports.borrow();
+    move_lamports(gateway_account, admin, balance)?;
+    gateway_account.try_borrow_mut_data()?.fill(0);
+
+    msg!("gateway closed, returned {} lamports", balance);
+    Ok(())
+}
+
 fn process_register_consumer(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
@@ -135,6 +406,7 @@ fn process_register_consumer(
     let system_program_account = next_account_info(&mut iter)?;
 
     require_signer(owner)?;
+    require_writable(gateway_account)?;
     require_writable(consumer_account)?;
 
     if *system_program_account.key != system_program::ID {
@@ -144,10 +416,13 @@ fn process_register_consumer(
         return Err(GatewayError::InvalidAccount.into());
     }
 
-    let gateway = read_gateway(gateway_account)?;
+    let mut gateway = read_gateway(gateway_account)?;
     if !gateway.is_initialized {
         return Err(GatewayError::InvalidAccount.into());
     }
+    if gateway.paused {
+        return Err(GatewayError::GatewayPaused.into());
+    }
 
     let (expected_consumer, bump) =
         consumer_pda(gateway_account.key, owner.key, api_key_id, program_id);
@@ -177,29 +452,55 @@ fn process_register_consumer(
 
     let now_ts = Clock::get()?.unix_timestamp;
     let consumer = ConsumerAccount {
+        discriminator: ConsumerAccount::DISCRIMINATOR,
+        version: ConsumerAccount::VERSION,
         is_initialized: true,
         gateway: *gateway_account.key,
         owner: *owner.key,
         api_key_id,
         api_key_hash,
+        previous_api_key_hash: [0u8; 32],
+        previous_key_expires_ts: 0,
+        status: ConsumerStatus::Active,
         bucket_tokens: gateway.bucket_capacity,
         bucket_last_refill_ts: now_ts,
-        quota_remaining: gateway.period_limit,
+        quota_remaining: initial_quota(gateway.period_limit),
         quota_period_start_ts: now_ts,
         total_calls: 0,
         total_spent_lamports: 0,
         bump,
+        plan: Pubkey::default(),
+        overrides: ConsumerOverrides::default(),
+        bucket_milli_remainder: 0,
+        window_previous_used: 0,
+        window_current_used: 0,
+        gcra_tat_ms: 0,
+        free_calls_used: 0,
+        free_period_start_ts: now_ts,
+        trial_calls_used: 0,
+        credit_limit_lamports: 0,
+        debt_lamports: 0,
+        token_vault: Pubkey::default(),
+        quota_period_limit: gateway.period_limit,
     };
 
     write_consumer(consumer_account, &consumer)?;
+
+    gateway.consumer_count = gateway
+        .consumer_count
+        .checked_add(1)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    write_gateway(gateway_account, &gateway)?;
+
     msg!("consumer registered");
     Ok(())
 }
 
-fn process_topup(accounts: &[AccountInfo], lamports: u64) -> ProgramResult {
+fn process_topup(program_id: &Pubkey, accounts: &[AccountInfo], lamports: u64) -> ProgramResult {
     let mut iter = accounts.iter();
     let owner = next_account_info(&mut iter)?;
     let consumer_account = next_account_info(&mut iter)?;
+    let gateway_account = next_account_info(&mut iter)?;
     let system_program_account = next_account_info(&mut iter)?;
 
     require_signer(owner)?;
@@ -212,6 +513,26 @@ fn process_topup(accounts: &[AccountInfo], lamports: u64) -> ProgramResult {
     if !consumer.is_initialized || consumer.owner != *owner.key {
         return Err(GatewayError::Unauthorized.into());
     }
+    if consumer.gateway != *gateway_account.key || gateway_account.owner != program_id {
+        return Err(GatewayError::InvalidAccount.into());
+    }
+    // Lamports sent to a token-billing consumer could never pay for a call.
+    if read_gateway(gateway_account)?.has_token_billing() && !consumer.has_token_vault() {
+        msg!("consumer has no token vault");
+        return Err(GatewayError::InvalidAccount.into());
+    }
+
+    if consumer.has_token_vault() {
+        let source = next_account_info(&mut iter)?;
+        let vault = next_account_info(&mut iter)?;
+        let mint = next_account_info(&mut iter)?;
+        let token_program = next_account_info(&mut iter)?;
+        if *vault.key != consumer.token_vault {
+            return Err(GatewayError::InvalidAccount.into());
+        }
+        transfer_tokens(owner, source, mint, vault, token_program, lamports, &[])?;
+        return Ok(());
+    }
 
     invoke(
         &system_instruction::transfer(owner.key, consumer_account.key, lamports),
@@ -225,158 +546,2145 @@ fn process_topup(accounts: &[AccountInfo], lamports: u64) -> ProgramResult {
     Ok(())
 }
 
-fn process_consume(
-    accounts: &[AccountInfo],
-    api_key_id: u64,
-    presented_api_key_hash: [u8; 32],
-) -> ProgramResult {
+fn process_withdraw(program_id: &Pubkey, accounts: &[AccountInfo], lamports: u64) -> ProgramResult {
     let mut iter = accounts.iter();
-    let backend = next_account_info(&mut iter)?;
-    let gateway_account = next_account_info(&mut iter)?;
+    let owner = next_account_info(&mut iter)?;
     let consumer_account = next_account_info(&mut iter)?;
-    let treasury_account = next_account_info(&mut iter)?;
 
-    require_signer(backend)?;
+    require_signer(owner)?;
+    require_writable(owner)?;
     require_writable(consumer_account)?;
-    require_writable(treasury_account)?;
 
-    let gateway = read_gateway(gateway_account)?;
-    if !gateway.is_initialized {
-        return Err(GatewayError::InvalidAccount.into());
+    let consumer = read_owned_consumer(program_id, owner, consumer_account)?;
+    if consumer.debt_lamports > 0 {
+        msg!("settle {} lamports of debt first", consumer.debt_lamports);
+        return Err(GatewayError::DebtOutstanding.into());
     }
-    if gateway.backend_signer != *backend.key {
-        return Err(GatewayError::Unauthorized.into());
-    }
-    if gateway.treasury != *treasury_account.key {
-        return Err(GatewayError::InvalidAccount.into());
+
+    if consumer.has_token_vault() {
+        let destination = next_account_info(&mut iter)?;
+        let vault = next_account_info(&mut iter)?;
+        let mint = next_account_info(&mut iter)?;
+        let token_program = next_account_info(&mut iter)?;
+        require_writable(vault)?;
+        if *vault.key != consumer.token_vault {
+            return Err(GatewayError::InvalidAccount.into());
+        }
+        if read_token_account(vault, token_program.key)?.amount < lamports {
+            return Err(GatewayError::InsufficientBalance.into());
+        }
+
+        transfer_from_vault(
+            consumer_account,
+            &consumer,
+            vault,
+            mint,
+            destination,
+            token_program,
+            lamports,
+        )?;
+        msg!("withdrew {} tokens", lamports);
+        return Ok(());
     }
 
-    let mut consumer = read_consumer(consumer_account)?;
-    if !consumer.is_initialized {
-        return Err(GatewayError::InvalidAccount.into());
+    let available_balance = **consumer_account.lamports.borrow();
+    let minimum_rent = Rent::get()?.minimum_balance(ConsumerAccount::LEN);
+    if !can_charge(available_balance, minimum_rent, lamports) {
+        return Err(GatewayError::InsufficientBalance.into());
     }
+
+    move_lamports(consumer_account, owner, lamports)?;
+    msg!("withdrew {} lamports", lamports);
+    Ok(())
+}
+
+fn process_close_consumer(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let mut iter = accounts.iter();
+    let owner = next_account_info(&mut iter)?;
+    let consumer_account = next_account_info(&mut iter)?;
+    let gateway_account = next_account_info(&mut iter)?;
+
+    require_signer(owner)?;
+    require_writable(owner)?;
+    require_writable(consumer_account)?;
+    require_writable(gateway_account)?;
+
+    let consumer = read_owned_consumer(program_id, owner, consumer_account)?;
     if consumer.gateway != *gateway_account.key {
         return Err(GatewayError::InvalidAccount.into());
     }
-    if consumer.api_key_id != api_key_id || consumer.api_key_hash != presented_api_key_hash {
-        return Err(GatewayError::ApiKeyMismatch.into());
+    if consumer.debt_lamports > 0 {
+        msg!("settle {} lamports of debt first", consumer.debt_lamports);
+        return Err(GatewayError::DebtOutstanding.into());
     }
 
-    let rules = GatewayRules {
-        base_price_lamports: gateway.base_price_lamports,
-        max_surge_bps: gateway.max_surge_bps,
-        period_limit: gateway.period_limit,
-        period_seconds: gateway.period_seconds,
-        bucket_capacity: gateway.bucket_capacity,
-        refill_per_second: gateway.refill_per_second,
-    };
-
-    let mut runtime = ConsumerRuntimeState {
-        bucket_tokens: consumer.bucket_tokens,
-        bucket_last_refill_ts: consumer.bucket_last_refill_ts,
-        quota_remaining: consumer.quota_remaining,
-        quota_period_start_ts: consumer.quota_period_start_ts,
-        total_calls: consumer.total_calls,
-        total_spent_lamports: consumer.total_spent_lamports,
-    };
+    // Consumers of a closed gateway can still leave; there is no count to update.
+    if gateway_account.owner == program_id {
+        // Closing would clear the status and let the owner register afresh.
+        if consumer.status != ConsumerStatus::Active {
+            return Err(GatewayError::ConsumerSuspended.into());
+        }
+        let mut gateway = read_gateway(gateway_account)?;
+        gateway.consumer_count = gateway.consumer_count.saturating_sub(1);
+        write_gateway(gateway_account, &gateway)?;
+    }
 
-    let now_ts = Clock::get()?.unix_timestamp;
-    let available_balance = **consumer_account.lamports.borrow();
-    let minimum_rent = Rent::get()?.minimum_balance(ConsumerAccount::LEN);
+    if consumer.has_plan() {
+        let plan_account = next_account_info(&mut iter)?;
+        require_writable(plan_account)?;
+        if *plan_account.key != consumer.plan {
+            return Err(GatewayError::InvalidAccount.into());
+        }
+        let mut plan = read_gateway_plan(program_id, gateway_account.key, plan_account)?;
+        plan.consumer_count = plan.consumer_count.saturating_sub(1);
+        write_plan(plan_account, &plan)?;
+    }
 
-    let charge = apply_consume(
-        &rules,
-        &mut runtime,
-        now_ts,
-        available_balance,
-        minimum_rent,
-    )
-    .map_err(map_consume_error)?;
+    if consumer.has_token_vault() {
+        let vault = next_account_info(&mut iter)?;
+        let mint = next_account_info(&mut iter)?;
+        let token_program = next_account_info(&mut iter)?;
+        require_writable(vault)?;
+        require_writable(mint)?;
+        let vault_state = read_token_account(vault, token_program.key)?;
+        if *vault.key != consumer.token_vault || *mint.key != vault_state.mint {
+            return Err(GatewayError::InvalidAccount.into());
+        }
+        read_mint(mint, token_program.key)?;
+        let remaining = vault_state.amount;
+        if remaining > 0 {
+            msg!("withdraw the {} tokens left in the vault first", remaining);
+            return Err(GatewayError::InvalidInstruction.into());
+        }
 
-    {
-        let mut source = consumer_account.try_borrow_mut_lamports()?;
-        if **source < charge {
-            return Err(GatewayError::InsufficientBalance.into());
+        // Fees withheld on top-ups stay in the vault and would block closing it.
+        if withheld_transfer_fees(&vault.try_borrow_data()?)? > 0 {
+            invoke(
+                &spl_token_2022::extension::transfer_fee::instruction::harvest_withheld_tokens_to_mint(
+                    token_program.key,
+                    mint.key,
+                    &[vault.key],
+                )?,
+                &[mint.clone(), vault.clone(), token_program.clone()],
+            )?;
         }
-        **source -= charge;
-    }
 
-    {
-        let mut dest = treasury_account.try_borrow_mut_lamports()?;
-        **dest = (**dest)
-            .checked_add(charge)
-            .ok_or(ProgramError::ArithmeticOverflow)?;
+        let api_key_id = consumer.api_key_id.to_le_bytes();
+        let bump = [consumer.bump];
+        invoke_signed(
+            &spl_token_2022::instruction::close_account(
+                token_program.key,
+                vault.key,
+                owner.key,
+                consumer_account.key,
+                &[],
+            )?,
+            &[
+                vault.clone(),
+                owner.clone(),
+                consumer_account.clone(),
+                token_program.clone(),
+            ],
+            &[&[
+                b"consumer",
+                consumer.gateway.as_ref(),
+                consumer.owner.as_ref(),
+                &api_key_id,
+                &bump,
+            ]],
+        )?;
     }
 
-    consumer.bucket_tokens = runtime.bucket_tokens;
-    consumer.bucket_last_refill_ts = runtime.bucket_last_refill_ts;
-    consumer.quota_remaining = runtime.quota_remaining;
-    consumer.quota_period_start_ts = runtime.quota_period_start_ts;
-    consumer.total_calls = runtime.total_calls;
-    consumer.total_spent_lamports = runtime.total_spent_lamports;
+    let balance = **consumer_account.lamports.borrow();
+    move_lamports(consumer_account, owner, balance)?;
+    consumer_account.try_borrow_mut_data()?.fill(0);
 
-    write_consumer(consumer_account, &consumer)?;
+    msg!("consumer closed, returned {} lamports", balance);
     Ok(())
 }
 
-fn create_pda_account<'a>(
-    payer: &AccountInfo<'a>,
-    pda: &AccountInfo<'a>,
-    system_program_account: &AccountInfo<'a>,
+fn process_rotate_api_key(
     program_id: &Pubkey,
-    signer_seeds: &[&[u8]],
-    data_len: usize,
+    accounts: &[AccountInfo],
+    new_hash: [u8; 32],
+    grace_seconds: i64,
 ) -> ProgramResult {
-    if pda.owner == program_id && pda.data_len() == data_len {
-        return Ok(());
-    }
+    let mut iter = accounts.iter();
+    let owner = next_account_info(&mut iter)?;
+    let consumer_account = next_account_info(&mut iter)?;
 
-    if pda.owner != &system_program::ID {
-        return Err(GatewayError::InvalidAccount.into());
+    require_signer(owner)?;
+    require_writable(consumer_account)?;
+
+    if grace_seconds < 0 {
+        return Err(GatewayError::InvalidInstruction.into());
     }
 
-    let rent = Rent::get()?;
-    let lamports = rent.minimum_balance(data_len);
+    let mut consumer = read_owned_consumer(program_id, owner, consumer_account)?;
+    let now_ts = Clock::get()?.unix_timestamp;
 
-    invoke_signed(
-        &system_instruction::create_account(
-            payer.key,
-            pda.key,
-            lamports,
-            data_len as u64,
-            program_id,
-        ),
-        &[payer.clone(), pda.clone(), system_program_account.clone()],
-        &[signer_seeds],
-    )
-}
+    consumer.previous_api_key_hash = consumer.api_key_hash;
+    consumer.previous_key_expires_ts = now_ts.saturating_add(grace_seconds);
+    consumer.api_key_hash = new_hash;
 
-fn read_gateway(account: &AccountInfo) -> Result<GatewayConfig, ProgramError> {
-    if account.data_len() != GatewayConfig::LEN {
-        return Err(GatewayError::InvalidAccount.into());
-    }
-    GatewayConfig::try_from_slice(&account.try_borrow_data()?)
-        .map_err(|_| ProgramError::InvalidAccountData)
+    write_consumer(consumer_account, &consumer)?;
+    msg!(
+        "api key rotated, previous key valid until {}",
+        consumer.previous_key_expires_ts
+    );
+    Ok(())
 }
 
-fn write_gateway(account: &AccountInfo, cfg: &GatewayConfig) -> ProgramResult {
-    let mut data = account.try_borrow_mut_data()?;
-    cfg.serialize(&mut &mut data[..])
-        .map_err(|_| ProgramError::InvalidAccountData)
-}
+fn process_set_consumer_status(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    status: ConsumerStatus,
+) -> ProgramResult {
+    let mut iter = accounts.iter();
+    let admin = next_account_info(&mut iter)?;
+    let gateway_account = next_account_info(&mut iter)?;
+    let consumer_account = next_account_info(&mut iter)?;
 
-fn read_consumer(account: &AccountInfo) -> Result<ConsumerAccount, ProgramError> {
-    if account.data_len() != ConsumerAccount::LEN {
+    require_signer(admin)?;
+    require_writable(consumer_account)?;
+
+    read_admin_gateway(program_id, admin, gateway_account)?;
+
+    if consumer_account.owner != program_id {
         return Err(GatewayError::InvalidAccount.into());
     }
-    ConsumerAccount::try_from_slice(&account.try_borrow_data()?)
-        .map_err(|_| ProgramError::InvalidAccountData)
+    let mut consumer = read_consumer(consumer_account)?;
+    if !consumer.is_initialized || consumer.gateway != *gateway_account.key {
+        return Err(GatewayError::InvalidAccount.into());
+    }
+    if consumer.status == ConsumerStatus::Revoked {
+        return Err(GatewayError::Unauthorized.into());
+    }
+
+    consumer.status = status;
+
+    write_consumer(consumer_account, &consumer)?;
+    msg!("consumer status set to {:?}", status);
+    Ok(())
 }
 
-fn write_consumer(account: &AccountInfo, consumer: &ConsumerAccount) -> ProgramResult {
-    let mut data = account.try_borrow_mut_data()?;
-    consumer
-        .serialize(&mut &mut data[..])
+fn process_set_consumer_overrides(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    overrides: ConsumerOverrides,
+) -> ProgramResult {
+    let mut iter = accounts.iter();
+    let admin = next_account_info(&mut iter)?;
+    let gateway_account = next_account_info(&mut iter)?;
+    let consumer_account = next_account_info(&mut iter)?;
+
+    require_signer(admin)?;
+    require_writable(consumer_account)?;
+
+    if overrides.discount_bps > 10_000 {
+        return Err(GatewayError::InvalidInstruction.into());
+    }
+
+    read_admin_gateway(program_id, admin, gateway_account)?;
+
+    if consumer_account.owner != program_id {
+        return Err(GatewayError::InvalidAccount.into());
+    }
+    let mut consumer = read_consumer(consumer_account)?;
+    if !consumer.is_initialized || consumer.gateway != *gateway_account.key {
+        return Err(GatewayError::InvalidAccount.into());
+    }
+
+    consumer.overrides = overrides;
+
+    write_consumer(consumer_account, &consumer)?;
+    msg!("consumer overrides set");
+    Ok(())
+}
+
+fn process_set_billing_mint(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let mut iter = accounts.iter();
+    let admin = next_account_info(&mut iter)?;
+    let gateway_account = next_account_info(&mut iter)?;
+    let mint = next_account_info(&mut iter)?;
+    let treasury_account = next_account_info(&mut iter)?;
+
+    require_signer(admin)?;
+    require_writable(gateway_account)?;
+
+    let mut cfg = read_admin_gateway(program_id, admin, gateway_account)?;
+    if cfg.consumer_count > 0 {
+        msg!("{} consumers still registered", cfg.consumer_count);
+        return Err(GatewayError::ConsumersOutstanding.into());
+    }
+
+    let decimals = read_mint(mint, mint.owner)?.decimals;
+    if read_token_account(treasury_account, mint.owner)?.mint != *mint.key {
+        return Err(GatewayError::InvalidAccount.into());
+    }
+
+    cfg.billing_mint = *mint.key;
+    cfg.billing_decimals = decimals;
+    cfg.treasury = *treasury_account.key;
+
+    write_gateway(gateway_account, &cfg)?;
+    msg!("billing in mint {}", mint.key);
+    Ok(())
+}
+
+fn process_open_token_vault(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let mut iter = accounts.iter();
+    let owner = next_account_info(&mut iter)?;
+    let gateway_account = next_account_info(&mut iter)?;
+    let consumer_account = next_account_info(&mut iter)?;
+    let vault = next_account_info(&mut iter)?;
+    let mint = next_account_info(&mut iter)?;
+    let token_program = next_account_info(&mut iter)?;
+    let system_program_account = next_account_info(&mut iter)?;
+
+    require_signer(owner)?;
+    require_writable(owner)?;
+    require_writable(consumer_account)?;
+    require_writable(vault)?;
+
+    if *system_program_account.key != system_program::ID || mint.owner != token_program.key {
+        return Err(GatewayError::InvalidAccount.into());
+    }
+
+    let mut consumer = read_owned_consumer(program_id, owner, consumer_account)?;
+    if consumer.gateway != *gateway_account.key || gateway_account.owner != program_id {
+        return Err(GatewayError::InvalidAccount.into());
+    }
+    if consumer.has_token_vault() {
+        return Err(GatewayError::AlreadyInitialized.into());
+    }
+    let gateway = read_gateway(gateway_account)?;
+    if !gateway.has_token_billing() || gateway.billing_mint != *mint.key {
+        return Err(GatewayError::InvalidAccount.into());
+    }
+
+    let (expected_vault, bump) = consumer_vault_pda(consumer_account.key, program_id);
+    if expected_vault != *vault.key {
+        return Err(GatewayError::InvalidAccount.into());
+    }
+
+    // Token-2022 mints may require extensions on every account, e.g. the
+    // withheld amount of a transfer-fee mint.
+    let vault_len = {
+        let mint_data = mint.try_borrow_data()?;
+        let mint_extensions =
+            StateWithExtensions::<Mint>::unpack(&mint_data)?.get_extension_types()?;
+        ExtensionType::try_calculate_account_len::<TokenAccount>(
+            &ExtensionType::get_required_init_account_extensions(&mint_extensions),
+        )?
+    };
+
+    create_pda_account(
+        owner,
+        vault,
+        system_program_account,
+        token_program.key,
+        &[b"vault", consumer_account.key.as_ref(), &[bump]],
+        vault_len,
+    )?;
+    invoke(
+        &spl_token_2022::instruction::initialize_account3(
+            token_program.key,
+            vault.key,
+            mint.key,
+            consumer_account.key,
+        )?,
+        &[vault.clone(), mint.clone(), token_program.clone()],
+    )?;
+
+    consumer.token_vault = *vault.key;
+    write_consumer(consumer_account, &consumer)?;
+    msg!("token vault {} opened", vault.key);
+    Ok(())
+}
+
+fn process_set_credit_limit(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    credit_limit_lamports: u64,
+) -> ProgramResult {
+    let mut iter = accounts.iter();
+    let admin = next_account_info(&mut iter)?;
+    let gateway_account = next_account_info(&mut iter)?;
+    let consumer_account = next_account_info(&mut iter)?;
+
+    require_signer(admin)?;
+    require_writable(consumer_account)?;
+
+    read_admin_gateway(program_id, admin, gateway_account)?;
+
+    if consumer_account.owner != program_id {
+        return Err(GatewayError::InvalidAccount.into());
+    }
+    let mut consumer = read_consumer(consumer_account)?;
+    if !consumer.is_initialized || consumer.gateway != *gateway_account.key {
+        return Err(GatewayError::InvalidAccount.into());
+    }
+
+    consumer.credit_limit_lamports = credit_limit_lamports;
+
+    write_consumer(consumer_account, &consumer)?;
+    msg!("credit limit set to {} lamports", credit_limit_lamports);
+    Ok(())
+}
+
+fn process_settle_debt(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    lamports: u64,
+) -> ProgramResult {
+    let mut iter = accounts.iter();
+    let owner = next_account_info(&mut iter)?;
+    let consumer_account = next_account_info(&mut iter)?;
+    let gateway_account = next_account_info(&mut iter)?;
+    let treasury_account = next_account_info(&mut iter)?;
+    let system_program_account = next_account_info(&mut iter)?;
+
+    require_signer(owner)?;
+    require_writable(owner)?;
+    require_writable(consumer_account)?;
+    require_writable(treasury_account)?;
+
+    if *system_program_account.key != system_program::ID {
+        return Err(GatewayError::InvalidAccount.into());
+    }
+
+    let mut consumer = read_owned_consumer(program_id, owner, consumer_account)?;
+    if consumer.gateway != *gateway_account.key || gateway_account.owner != program_id {
+        return Err(GatewayError::InvalidAccount.into());
+    }
+    let gateway = read_gateway(gateway_account)?;
+    if gateway.treasury != *treasury_account.key {
+        return Err(GatewayError::InvalidAccount.into());
+    }
+    if gateway.has_token_billing() && !consumer.has_token_vault() {
+        msg!("consumer has no token vault");
+        return Err(GatewayError::InvalidAccount.into());
+    }
+
+    let mut settled = lamports.min(consumer.debt_lamports);
+    let surplus = lamports - settled;
+    if consumer.has_token_vault() {
+        let source = next_account_info(&mut iter)?;
+        let vault = next_account_info(&mut iter)?;
+        let mint = next_account_info(&mut iter)?;
+        let token_program = next_account_info(&mut iter)?;
+        if *vault.key != consumer.token_vault {
+            return Err(GatewayError::InvalidAccount.into());
+        }
+        if surplus > 0 {
+            transfer_tokens(owner, source, mint, vault, token_program, surplus, &[])?;
+        }
+        if settled > 0 {
+            // Only what reaches the treasury pays the debt down.
+            settled -= transfer_tokens(
+                owner,
+                source,
+                mint,
+                treasury_account,
+                token_program,
+                settled,
+                &[],
+            )?;
+        }
+    } else {
+        for (destination, amount) in [(treasury_account, settled), (consumer_account, surplus)] {
+            if amount == 0 {
+                continue;
+            }
+            invoke(
+                &system_instruction::transfer(owner.key, destination.key, amount),
+                &[
+                    owner.clone(),
+                    destination.clone(),
+                    system_program_account.clone(),
+                ],
+            )?;
+        }
+    }
+
+    consumer.debt_lamports -= settled;
+    write_consumer(consumer_account, &consumer)?;
+
+    msg!(
+        "settled {} lamports, {} still owed, {} topped up",
+        settled,
+        consumer.debt_lamports,
+        surplus
+    );
+    Ok(())
+}
+
+fn process_consume(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    api_key_id: u64,
+    presented_api_key_hash: [u8; 32],
+    units: u64,
+    route_id: Option<u64>,
+) -> ProgramResult {
+    let mut iter = accounts.iter();
+    let backend = next_account_info(&mut iter)?;
+    let gateway_account = next_account_info(&mut iter)?;
+    let consumer_account = next_account_info(&mut iter)?;
+    let treasury_account = next_account_info(&mut iter)?;
+
+    let ctx = load_consume_context(
+        program_id,
+        backend,
+        gateway_account,
+        treasury_account,
+        iter.as_slice(),
+    )?;
+    let mut retry_after_ms = None;
+    let result = charge_consumer(
+        &ctx,
+        consumer_account,
+        api_key_id,
+        presented_api_key_hash,
+        units,
+        route_id,
+        &mut retry_after_ms,
+    );
+    // Clients read the wait from the failed transaction's return data.
+    if let Some(wait_ms) = retry_after_ms {
+        set_return_data(&wait_ms.to_le_bytes());
+    }
+    result?;
+    Ok(())
+}
+
+fn process_consume_batch(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    entries: Vec<BatchConsumeEntry>,
+) -> ProgramResult {
+    let mut iter = accounts.iter();
+    let backend = next_account_info(&mut iter)?;
+    let gateway_account = next_account_info(&mut iter)?;
+    let treasury_account = next_account_info(&mut iter)?;
+    let remaining = iter.as_slice();
+
+    if entries.is_empty() || entries.len() > MAX_BATCH_ENTRIES || entries.len() > remaining.len() {
+        return Err(GatewayError::InvalidInstruction.into());
+    }
+    let (consumer_accounts, extra_accounts) = remaining.split_at(entries.len());
+
+    let ctx = load_consume_context(
+        program_id,
+        backend,
+        gateway_account,
+        treasury_account,
+        extra_accounts,
+    )?;
+
+    let mut outcomes = Vec::with_capacity(entries.len());
+    for (index, (entry, consumer_account)) in entries.iter().zip(consumer_accounts).enumerate() {
+        let mut retry_after_ms = None;
+        let outcome = match charge_consumer(
+            &ctx,
+            consumer_account,
+            entry.api_key_id,
+            entry.presented_api_key_hash,
+            entry.units,
+            entry.route_id,
+            &mut retry_after_ms,
+        ) {
+            Ok(charged_lamports) => BatchConsumeOutcome {
+                charged_lamports,
+                error_code: 0,
+                retry_after_ms: 0,
+            },
+            Err(err) => {
+                msg!("batch entry {} failed: {}", index, err);
+                BatchConsumeOutcome {
+                    charged_lamports: 0,
+                    error_code: u64::from(err),
+                    retry_after_ms: retry_after_ms.unwrap_or(0),
+                }
+            }
+        };
+        outcomes.push(outcome);
+    }
+
+    let succeeded = outcomes.iter().filter(|o| o.error_code == 0).count();
+    msg!("batch consumed {}/{} entries", succeeded, outcomes.len());
+    set_return_data(&borsh::to_vec(&outcomes).map_err(|_| ProgramError::InvalidAccountData)?);
+    Ok(())
+}
+
+/// Gateway-level inputs shared by every consumer charged in one instruction.
+struct ConsumeContext<'a, 'info> {
+    program_id: &'a Pubkey,
+    gateway_key: &'a Pubkey,
+    gateway: GatewayConfig,
+    treasury_account: &'a AccountInfo<'info>,
+    /// Accounts after the fixed ones (route policies, plans), looked up by content.
+    extra_accounts: &'a [AccountInfo<'info>],
+    now_ts: i64,
+    minimum_rent: u64,
+}
+
+fn load_consume_context<'a, 'info>(
+    program_id: &'a Pubkey,
+    backend: &AccountInfo<'info>,
+    gateway_account: &'a AccountInfo<'info>,
+    treasury_account: &'a AccountInfo<'info>,
+    extra_accounts: &'a [AccountInfo<'info>],
+) -> Result<ConsumeContext<'a, 'info>, ProgramError> {
+    require_signer(backend)?;
+    require_writable(treasury_account)?;
+
+    let now_ts = Clock::get()?.unix_timestamp;
+
+    let gateway = read_gateway(gateway_account)?;
+    if !gateway.is_initialized {
+        return Err(GatewayError::InvalidAccount.into());
+    }
+    if !gateway.is_backend_signer(backend.key, now_ts) {
+        return Err(GatewayError::Unauthorized.into());
+    }
+    if gateway.paused {
+        return Err(GatewayError::GatewayPaused.into());
+    }
+    if gateway.treasury != *treasury_account.key {
+        return Err(GatewayError::InvalidAccount.into());
+    }
+
+    Ok(ConsumeContext {
+        program_id,
+        gateway_key: gateway_account.key,
+        gateway,
+        treasury_account,
+        extra_accounts,
+        now_ts,
+        minimum_rent: Rent::get()?.minimum_balance(ConsumerAccount::LEN),
+    })
+}
+
+/// Validates one consumer, applies the limits and moves the charge to the treasury.
+/// Nothing is written unless every check passes. A call rejected by the GCRA
+/// limiter stores its wait in `retry_after_ms`.
+fn charge_consumer<'info>(
+    ctx: &ConsumeContext<'_, 'info>,
+    consumer_account: &AccountInfo<'info>,
+    api_key_id: u64,
+    presented_api_key_hash: [u8; 32],
+    units: u64,
+    route_id: Option<u64>,
+    retry_after_ms: &mut Option<u64>,
+) -> Result<u64, ProgramError> {
+    require_writable(consumer_account)?;
+
+    if consumer_account.owner != ctx.program_id {
+        return Err(GatewayError::InvalidAccount.into());
+    }
+
+    let mut consumer = read_consumer(consumer_account)?;
+    if !consumer.is_initialized {
+        return Err(GatewayError::InvalidAccount.into());
+    }
+    if consumer.gateway != *ctx.gateway_key {
+        return Err(GatewayError::InvalidAccount.into());
+    }
+    if consumer.status != ConsumerStatus::Active {
+        return Err(GatewayError::ConsumerSuspended.into());
+    }
+    if consumer.api_key_id != api_key_id
+        || !consumer.accepts_api_key(&presented_api_key_hash, ctx.now_ts)
+    {
+        return Err(GatewayError::ApiKeyMismatch.into());
+    }
+
+    let mut congestion = None;
+    let stats = if ctx.gateway.has_stats() {
+        let (stats_account, mut stats) = find_gateway_stats(ctx)?;
+        let limits = AggregateLimits {
+            period_limit: stats.period_limit,
+            period_seconds: stats.period_seconds,
+            bucket_capacity: stats.bucket_capacity,
+            refill_per_second: stats.refill_per_second,
+        };
+        let mut usage = AggregateUsage {
+            bucket_tokens: stats.bucket_tokens,
+            bucket_last_refill_ts: stats.bucket_last_refill_ts,
+            quota_remaining: stats.quota_remaining,
+            quota_period_start_ts: stats.quota_period_start_ts,
+        };
+        apply_aggregate_limits(&limits, &mut usage, units, ctx.now_ts).map_err(
+            |err| match err {
+                ConsumeError::RateLimited | ConsumeError::QuotaExceeded => {
+                    GatewayError::GatewayOverloaded.into()
+                }
+                other => map_consume_error(other),
+            },
+        )?;
+
+        stats.bucket_tokens = usage.bucket_tokens;
+        stats.bucket_last_refill_ts = usage.bucket_last_refill_ts;
+        stats.quota_remaining = usage.quota_remaining;
+        stats.quota_period_start_ts = usage.quota_period_start_ts;
+
+        if stats.has_congestion_pricing() {
+            let mut window = SlidingWindowState {
+                period_seconds: stats.congestion_window_seconds,
+                window_start_ts: stats.congestion_window_start_ts,
+                previous_used: stats.congestion_previous_calls,
+                current_used: stats.congestion_current_calls,
+            };
+            let recent_calls = record_congestion(&mut window, units, ctx.now_ts);
+            stats.congestion_window_start_ts = window.window_start_ts;
+            stats.congestion_previous_calls = window.previous_used;
+            stats.congestion_current_calls = window.current_used;
+            congestion = Some(CongestionLoad {
+                recent_calls,
+                target_calls: stats.congestion_target_calls,
+            });
+        }
+        Some((stats_account, stats))
+    } else {
+        None
+    };
+
+    let mut rules = if consumer.has_plan() {
+        plan_rules(&find_plan(ctx, &consumer.plan)?, &ctx.gateway)
+    } else {
+        gateway_rules(&ctx.gateway)
+    };
+
+    let mut runtime = runtime_state(&consumer);
+
+    let route = match route_id {
+        Some(route_id) => {
+            let (route_account, mut route) = find_route_policy(ctx, route_id)?;
+            let limits = AggregateLimits {
+                period_limit: route.period_limit,
+                period_seconds: route.period_seconds,
+                bucket_capacity: route.bucket_capacity,
+                refill_per_second: route.refill_per_second,
+            };
+            let mut usage = AggregateUsage {
+                bucket_tokens: route.bucket_tokens,
+                bucket_last_refill_ts: route.bucket_last_refill_ts,
+                quota_remaining: route.quota_remaining,
+                quota_period_start_ts: route.quota_period_start_ts,
+            };
+            apply_aggregate_limits(&limits, &mut usage, units, ctx.now_ts)
+                .map_err(map_consume_error)?;
+
+            route.bucket_tokens = usage.bucket_tokens;
+            route.bucket_last_refill_ts = usage.bucket_last_refill_ts;
+            route.quota_remaining = usage.quota_remaining;
+            route.quota_period_start_ts = usage.quota_period_start_ts;
+
+            rules.base_price_lamports = route.base_price_lamports;
+            rules.max_surge_bps = route.max_surge_bps;
+            Some((route_account, route))
+        }
+        None => None,
+    };
+    rules.congestion = congestion;
+    let rules = apply_overrides(&rules, &consumer.overrides);
+
+    let vault = if ctx.gateway.has_token_billing() {
+        Some(find_token_vault(ctx, &consumer)?)
+    } else {
+        None
+    };
+    let (available_balance, minimum_rent) = match &vault {
+        Some((_, amount)) => (*amount, 0),
+        None => (**consumer_account.lamports.borrow(), ctx.minimum_rent),
+    };
+
+    let charge = apply_consume_units(
+        &rules,
+        &mut runtime,
+        units,
+        ctx.now_ts,
+        available_balance.saturating_add(consumer.credit_headroom()),
+        minimum_rent,
+    )
+    .map_err(|err| {
+        if let Some(wait_ms) = gcra_retry_after_ms(&rules, &runtime, units, ctx.now_ts) {
+            msg!("rate limited, retry after {} ms", wait_ms);
+            *retry_after_ms = Some(wait_ms);
+        }
+        map_consume_error(err)
+    })?;
+
+    let (paid, debt) = split_charge(
+        available_balance,
+        minimum_rent,
+        charge,
+        consumer.debt_lamports,
+        consumer.credit_limit_lamports,
+    )
+    .ok_or(GatewayError::InsufficientBalance)?;
+    match vault {
+        Some((vault_account, _)) if paid > 0 => {
+            let mint = find_extra_account(ctx, &ctx.gateway.billing_mint, "billing mint")?;
+            let withheld = transfer_from_vault(
+                consumer_account,
+                &consumer,
+                vault_account,
+                mint,
+                ctx.treasury_account,
+                find_extra_account(ctx, mint.owner, "token program")?,
+                paid,
+            )?;
+            // Spend tracks what the treasury receives, not what the mint withholds.
+            runtime.total_spent_lamports = runtime.total_spent_lamports.saturating_sub(withheld);
+        }
+        Some(_) => {}
+        None => move_lamports(consumer_account, ctx.treasury_account, paid)?,
+    }
+    consumer.debt_lamports = debt;
+
+    store_runtime_state(&mut consumer, &runtime);
+    write_consumer(consumer_account, &consumer)?;
+    if let Some((route_account, route)) = &route {
+        write_route_policy(route_account, route)?;
+    }
+    if let Some((stats_account, stats)) = &stats {
+        write_gateway_stats(stats_account, stats)?;
+    }
+    Ok(charge)
+}
+
+/// Finds the consumer's token vault among the extra accounts, with its balance.
+fn find_token_vault<'a, 'info>(
+    ctx: &ConsumeContext<'a, 'info>,
+    consumer: &ConsumerAccount,
+) -> Result<(&'a AccountInfo<'info>, u64), ProgramError> {
+    if !consumer.has_token_vault() {
+        msg!("consumer has no token vault");
+        return Err(GatewayError::InvalidAccount.into());
+    }
+    let vault = find_extra_account(ctx, &consumer.token_vault, "token vault")?;
+    require_writable(vault)?;
+    let amount = read_token_account(vault, vault.owner)?.amount;
+    Ok((vault, amount))
+}
+
+fn find_extra_account<'a, 'info>(
+    ctx: &ConsumeContext<'a, 'info>,
+    key: &Pubkey,
+    name: &str,
+) -> Result<&'a AccountInfo<'info>, ProgramError> {
+    ctx.extra_accounts
+        .iter()
+        .find(|account| account.key == key)
+        .ok_or_else(|| {
+            msg!("{} {} not provided", name, key);
+            GatewayError::InvalidAccount.into()
+        })
+}
+
+/// Finds the gateway's registered `GatewayStats` among the extra accounts.
+fn find_gateway_stats<'a, 'info>(
+    ctx: &ConsumeContext<'a, 'info>,
+) -> Result<(&'a AccountInfo<'info>, GatewayStats), ProgramError> {
+    let Some(account) = ctx
+        .extra_accounts
+        .iter()
+        .find(|account| *account.key == ctx.gateway.stats)
+    else {
+        msg!("gateway stats {} not provided", ctx.gateway.stats);
+        return Err(GatewayError::InvalidAccount.into());
+    };
+
+    require_writable(account)?;
+    if account.owner != ctx.program_id {
+        return Err(GatewayError::InvalidAccount.into());
+    }
+    let stats = read_gateway_stats(account)?;
+    if !stats.is_initialized || stats.gateway != *ctx.gateway_key {
+        return Err(GatewayError::InvalidAccount.into());
+    }
+    Ok((account, stats))
+}
+
+/// Finds the initialized `RoutePolicy` for `route_id` among the extra accounts.
+fn find_route_policy<'a, 'info>(
+    ctx: &ConsumeContext<'a, 'info>,
+    route_id: u64,
+) -> Result<(&'a AccountInfo<'info>, RoutePolicy), ProgramError> {
+    for account in ctx.extra_accounts {
+        if account.owner != ctx.program_id
+            || stored_layout::<RoutePolicy>(&account.try_borrow_data()?) != StoredLayout::Current
+        {
+            continue;
+        }
+        let route = read_route_policy(account)?;
+        if route.is_initialized && route.gateway == *ctx.gateway_key && route.route_id == route_id {
+            require_writable(account)?;
+            return Ok((account, route));
+        }
+    }
+
+    msg!("route policy {} not provided", route_id);
+    Err(GatewayError::InvalidAccount.into())
+}
+
+/// Finds the consumer's assigned plan among the extra accounts.
+fn find_plan(ctx: &ConsumeContext, plan_key: &Pubkey) -> Result<Plan, ProgramError> {
+    match ctx
+        .extra_accounts
+        .iter()
+        .find(|account| account.key == plan_key)
+    {
+        Some(account) => read_gateway_plan(ctx.program_id, ctx.gateway_key, account),
+        None => {
+            msg!("plan {} not provided", plan_key);
+            Err(GatewayError::InvalidAccount.into())
+        }
+    }
+}
+
+fn gateway_rules(cfg: &GatewayConfig) -> GatewayRules {
+    GatewayRules {
+        base_price_lamports: cfg.base_price_lamports,
+        max_surge_bps: cfg.max_surge_bps,
+        period_limit: cfg.period_limit,
+        period_seconds: cfg.period_seconds,
+        bucket_capacity: cfg.bucket_capacity,
+        refill_per_second: cfg.refill_per_second,
+        pricing: cfg.pricing,
+        volume_discounts: cfg.volume_discounts,
+        refill_milli_tokens: cfg.refill_milli_tokens,
+        refill_interval_seconds: cfg.refill_interval_seconds,
+        quota_mode: cfg.quota_mode,
+        limiter: gateway_limiter(cfg),
+        congestion: None,
+        free_calls_per_period: cfg.free_calls_per_period,
+        trial_calls: cfg.trial_calls,
+    }
+}
+
+fn gateway_limiter(cfg: &GatewayConfig) -> RateLimiter {
+    if cfg.gcra_emission_interval_ms == 0 {
+        return RateLimiter::TokenBucket;
+    }
+    RateLimiter::Gcra(GcraParams {
+        emission_interval_ms: cfg.gcra_emission_interval_ms,
+        burst_tolerance_ms: cfg.gcra_burst_tolerance_ms,
+    })
+}
+
+/// Plans carry their own prices and limits but share the gateway's price curve,
+/// volume discounts, quota mode and GCRA limiter.
+fn plan_rules(plan: &Plan, gateway: &GatewayConfig) -> GatewayRules {
+    GatewayRules {
+        base_price_lamports: plan.base_price_lamports,
+        max_surge_bps: plan.max_surge_bps,
+        period_limit: plan.period_limit,
+        period_seconds: plan.period_seconds,
+        bucket_capacity: plan.bucket_capacity,
+        refill_per_second: plan.refill_per_second,
+        pricing: gateway.pricing,
+        volume_discounts: gateway.volume_discounts,
+        refill_milli_tokens: plan.refill_milli_tokens,
+        refill_interval_seconds: plan.refill_interval_seconds,
+        quota_mode: gateway.quota_mode,
+        limiter: gateway_limiter(gateway),
+        congestion: None,
+        free_calls_per_period: plan.free_calls_per_period,
+        trial_calls: plan.trial_calls,
+    }
+}
+
+fn runtime_state(consumer: &ConsumerAccount) -> ConsumerRuntimeState {
+    ConsumerRuntimeState {
+        bucket_tokens: consumer.bucket_tokens,
+        bucket_last_refill_ts: consumer.bucket_last_refill_ts,
+        quota_remaining: consumer.quota_remaining,
+        quota_period_start_ts: consumer.quota_period_start_ts,
+        total_calls: consumer.total_calls,
+        total_spent_lamports: consumer.total_spent_lamports,
+        bucket_milli_remainder: consumer.bucket_milli_remainder,
+        window_previous_used: consumer.window_previous_used,
+        window_current_used: consumer.window_current_used,
+        gcra_tat_ms: consumer.gcra_tat_ms,
+        free_calls_used: consumer.free_calls_used,
+        free_period_start_ts: consumer.free_period_start_ts,
+        trial_calls_used: consumer.trial_calls_used,
+        quota_period_limit: consumer.quota_period_limit,
+    }
+}
+
+fn store_runtime_state(consumer: &mut ConsumerAccount, runtime: &ConsumerRuntimeState) {
+    consumer.bucket_tokens = runtime.bucket_tokens;
+    consumer.bucket_last_refill_ts = runtime.bucket_last_refill_ts;
+    consumer.quota_remaining = runtime.quota_remaining;
+    consumer.quota_period_start_ts = runtime.quota_period_start_ts;
+    consumer.total_calls = runtime.total_calls;
+    consumer.total_spent_lamports = runtime.total_spent_lamports;
+    consumer.bucket_milli_remainder = runtime.bucket_milli_remainder;
+    consumer.window_previous_used = runtime.window_previous_used;
+    consumer.window_current_used = runtime.window_current_used;
+    consumer.gcra_tat_ms = runtime.gcra_tat_ms;
+    consumer.free_calls_used = runtime.free_calls_used;
+    consumer.free_period_start_ts = runtime.free_period_start_ts;
+    consumer.trial_calls_used = runtime.trial_calls_used;
+    consumer.quota_period_limit = runtime.quota_period_limit;
+}
+
+#[allow(clippy::too_many_arguments)]
+fn process_update_gateway_config(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    base_price_lamports: Option<u64>,
+    max_surge_bps: Option<u16>,
+    period_limit: Option<u64>,
+    period_seconds: Option<i64>,
+    bucket_capacity: Option<u64>,
+    refill_per_second: Option<u64>,
+) -> ProgramResult {
+    let mut iter = accounts.iter();
+    let admin = next_account_info(&mut iter)?;
+    let gateway_account = next_account_info(&mut iter)?;
+
+    require_signer(admin)?;
+    require_writable(gateway_account)?;
+
+    let mut cfg = read_admin_gateway(program_id, admin, gateway_account)?;
+
+    if let Some(value) = base_price_lamports {
+        cfg.base_price_lamports = value;
+    }
+    if let Some(value) = max_surge_bps {
+        cfg.max_surge_bps = value;
+    }
+    if let Some(value) = period_limit {
+        cfg.period_limit = value;
+    }
+    if let Some(value) = period_seconds {
+        cfg.period_seconds = value;
+    }
+    if let Some(value) = bucket_capacity {
+        cfg.bucket_capacity = value;
+    }
+    if let Some(value) = refill_per_second {
+        cfg.refill_per_second = value;
+    }
+
+    write_gateway(gateway_account, &cfg)?;
+    msg!("gateway config updated");
+    Ok(())
+}
+
+fn process_set_pricing_model(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    model: PricingModel,
+) -> ProgramResult {
+    let mut iter = accounts.iter();
+    let admin = next_account_info(&mut iter)?;
+    let gateway_account = next_account_info(&mut iter)?;
+
+    require_signer(admin)?;
+    require_writable(gateway_account)?;
+
+    if !model.is_valid() {
+        return Err(GatewayError::InvalidInstruction.into());
+    }
+
+    let mut cfg = read_admin_gateway(program_id, admin, gateway_account)?;
+    cfg.pricing = model;
+
+    write_gateway(gateway_account, &cfg)?;
+    msg!("pricing model set to {:?}", model);
+    Ok(())
+}
+
+fn process_set_volume_discounts(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    tiers: Vec<VolumeDiscountTier>,
+) -> ProgramResult {
+    let mut iter = accounts.iter();
+    let admin = next_account_info(&mut iter)?;
+    let gateway_account = next_account_info(&mut iter)?;
+
+    require_signer(admin)?;
+    require_writable(gateway_account)?;
+
+    if !volume_tiers_are_valid(&tiers) {
+        return Err(GatewayError::InvalidInstruction.into());
+    }
+
+    let mut cfg = read_admin_gateway(program_id, admin, gateway_account)?;
+    cfg.volume_discounts = [VolumeDiscountTier::default(); MAX_VOLUME_DISCOUNT_TIERS];
+    cfg.volume_discounts[..tiers.len()].copy_from_slice(&tiers);
+
+    write_gateway(gateway_account, &cfg)?;
+    msg!("volume discount tiers set: {}", tiers.len());
+    Ok(())
+}
+
+fn process_set_fractional_refill(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    plan_id: Option<u64>,
+    refill_milli_tokens: u64,
+    refill_interval_seconds: i64,
+) -> ProgramResult {
+    let mut iter = accounts.iter();
+    let admin = next_account_info(&mut iter)?;
+    let gateway_account = next_account_info(&mut iter)?;
+
+    require_signer(admin)?;
+
+    if refill_interval_seconds < 0 || (refill_milli_tokens > 0 && refill_interval_seconds == 0) {
+        return Err(GatewayError::InvalidInstruction.into());
+    }
+
+    let mut cfg = read_admin_gateway(program_id, admin, gateway_account)?;
+
+    match plan_id {
+        Some(plan_id) => {
+            let plan_account = next_account_info(&mut iter)?;
+            require_writable(plan_account)?;
+            let mut plan = read_gateway_plan(program_id, gateway_account.key, plan_account)?;
+            if plan.plan_id != plan_id {
+                return Err(GatewayError::InvalidAccount.into());
+            }
+            plan.refill_milli_tokens = refill_milli_tokens;
+            plan.refill_interval_seconds = refill_interval_seconds;
+            write_plan(plan_account, &plan)?;
+        }
+        None => {
+            require_writable(gateway_account)?;
+            cfg.refill_milli_tokens = refill_milli_tokens;
+            cfg.refill_interval_seconds = refill_interval_seconds;
+            write_gateway(gateway_account, &cfg)?;
+        }
+    }
+
+    msg!(
+        "fractional refill set: {} milli-tokens every {}s",
+        refill_milli_tokens,
+        refill_interval_seconds
+    );
+    Ok(())
+}
+
+fn process_set_free_allowance(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    plan_id: Option<u64>,
+    free_calls_per_period: u64,
+    trial_calls: u64,
+) -> ProgramResult {
+    let mut iter = accounts.iter();
+    let admin = next_account_info(&mut iter)?;
+    let gateway_account = next_account_info(&mut iter)?;
+
+    require_signer(admin)?;
+
+    let mut cfg = read_admin_gateway(program_id, admin, gateway_account)?;
+
+    match plan_id {
+        Some(plan_id) => {
+            let plan_account = next_account_info(&mut iter)?;
+            require_writable(plan_account)?;
+            let mut plan = read_gateway_plan(program_id, gateway_account.key, plan_account)?;
+            if plan.plan_id != plan_id {
+                return Err(GatewayError::InvalidAccount.into());
+            }
+            plan.free_calls_per_period = free_calls_per_period;
+            plan.trial_calls = trial_calls;
+            write_plan(plan_account, &plan)?;
+        }
+        None => {
+            require_writable(gateway_account)?;
+            cfg.free_calls_per_period = free_calls_per_period;
+            cfg.trial_calls = trial_calls;
+            write_gateway(gateway_account, &cfg)?;
+        }
+    }
+
+    msg!(
+        "free allowance set: {} per period, {} trial",
+        free_calls_per_period,
+        trial_calls
+    );
+    Ok(())
+}
+
+fn process_set_quota_mode(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    mode: QuotaMode,
+) -> ProgramResult {
+    let mut iter = accounts.iter();
+    let admin = next_account_info(&mut iter)?;
+    let gateway_account = next_account_info(&mut iter)?;
+
+    require_signer(admin)?;
+    require_writable(gateway_account)?;
+
+    let mut cfg = read_admin_gateway(program_id, admin, gateway_account)?;
+    cfg.quota_mode = mode;
+
+    write_gateway(gateway_account, &cfg)?;
+    msg!("quota mode set to {:?}", mode);
+    Ok(())
+}
+
+fn process_set_gateway_capacity(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    limits: AggregateLimits,
+) -> ProgramResult {
+    let mut iter = accounts.iter();
+    let admin = next_account_info(&mut iter)?;
+    let gateway_account = next_account_info(&mut iter)?;
+    let stats_account = next_account_info(&mut iter)?;
+    let system_program_account = next_account_info(&mut iter)?;
+
+    require_signer(admin)?;
+    require_writable(gateway_account)?;
+    require_writable(stats_account)?;
+
+    if *system_program_account.key != system_program::ID {
+        return Err(GatewayError::InvalidAccount.into());
+    }
+
+    let mut cfg = read_admin_gateway(program_id, admin, gateway_account)?;
+
+    let (expected_stats, bump) = gateway_stats_pda(gateway_account.key, program_id);
+    if expected_stats != *stats_account.key {
+        return Err(GatewayError::InvalidAccount.into());
+    }
+
+    create_pda_account(
+        admin,
+        stats_account,
+        system_program_account,
+        program_id,
+        &[b"stats", gateway_account.key.as_ref(), &[bump]],
+        GatewayStats::LEN,
+    )?;
+
+    let mut stats = read_gateway_stats(stats_account)?;
+    if !stats.is_initialized {
+        let now_ts = Clock::get()?.unix_timestamp;
+        stats = GatewayStats {
+            discriminator: GatewayStats::DISCRIMINATOR,
+            version: GatewayStats::VERSION,
+            is_initialized: true,
+            gateway: *gateway_account.key,
+            bucket_tokens: limits.bucket_capacity,
+            bucket_last_refill_ts: now_ts,
+            quota_remaining: limits.period_limit,
+            quota_period_start_ts: now_ts,
+            bump,
+            ..GatewayStats::default()
+        };
+    } else {
+        let old_limits = AggregateLimits {
+            period_limit: stats.period_limit,
+            period_seconds: stats.period_seconds,
+            bucket_capacity: stats.bucket_capacity,
+            refill_per_second: stats.refill_per_second,
+        };
+        let mut usage = AggregateUsage {
+            bucket_tokens: stats.bucket_tokens,
+            bucket_last_refill_ts: stats.bucket_last_refill_ts,
+            quota_remaining: stats.quota_remaining,
+            quota_period_start_ts: stats.quota_period_start_ts,
+        };
+        rebase_aggregate_usage(
+            &old_limits,
+            &limits,
+            &mut usage,
+            Clock::get()?.unix_timestamp,
+        );
+
+        stats.bucket_tokens = usage.bucket_tokens;
+        stats.bucket_last_refill_ts = usage.bucket_last_refill_ts;
+        stats.quota_remaining = usage.quota_remaining;
+        stats.quota_period_start_ts = usage.quota_period_start_ts;
+    }
+
+    stats.period_limit = limits.period_limit;
+    stats.period_seconds = limits.period_seconds;
+    stats.bucket_capacity = limits.bucket_capacity;
+    stats.refill_per_second = limits.refill_per_second;
+    write_gateway_stats(stats_account, &stats)?;
+
+    if !cfg.has_stats() {
+        cfg.stats = expected_stats;
+        write_gateway(gateway_account, &cfg)?;
+    }
+
+    msg!("gateway capacity set");
+    Ok(())
+}
+
+fn process_set_congestion_pricing(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    window_seconds: i64,
+    target_calls: u64,
+) -> ProgramResult {
+    let mut iter = accounts.iter();
+    let admin = next_account_info(&mut iter)?;
+    let gateway_account = next_account_info(&mut iter)?;
+    let stats_account = next_account_info(&mut iter)?;
+
+    require_signer(admin)?;
+    require_writable(stats_account)?;
+
+    if target_calls > 0 && window_seconds <= 0 {
+        return Err(GatewayError::InvalidInstruction.into());
+    }
+
+    let cfg = read_admin_gateway(program_id, admin, gateway_account)?;
+    if !cfg.has_stats() || *stats_account.key != cfg.stats || stats_account.owner != program_id {
+        msg!("set the gateway capacity first");
+        return Err(GatewayError::InvalidAccount.into());
+    }
+
+    let mut stats = read_gateway_stats(stats_account)?;
+    if stats.congestion_window_seconds != window_seconds {
+        stats.congestion_window_start_ts = Clock::get()?.unix_timestamp;
+        stats.congestion_previous_calls = 0;
+        stats.congestion_current_calls = 0;
+    }
+    stats.congestion_window_seconds = if target_calls == 0 { 0 } else { window_seconds };
+    stats.congestion_target_calls = target_calls;
+
+    write_gateway_stats(stats_account, &stats)?;
+    msg!(
+        "congestion pricing set: {} calls per {} s",
+        target_calls,
+        stats.congestion_window_seconds
+    );
+    Ok(())
+}
+
+fn process_set_gcra_limiter(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    emission_interval_ms: u64,
+    burst_tolerance_ms: u64,
+) -> ProgramResult {
+    let mut iter = accounts.iter();
+    let admin = next_account_info(&mut iter)?;
+    let gateway_account = next_account_info(&mut iter)?;
+
+    require_signer(admin)?;
+    require_writable(gateway_account)?;
+
+    let mut cfg = read_admin_gateway(program_id, admin, gateway_account)?;
+    cfg.gcra_emission_interval_ms = emission_interval_ms;
+    cfg.gcra_burst_tolerance_ms = if emission_interval_ms == 0 {
+        0
+    } else {
+        burst_tolerance_ms
+    };
+
+    write_gateway(gateway_account, &cfg)?;
+    msg!(
+        "gcra limiter set: emission {} ms, burst tolerance {} ms",
+        cfg.gcra_emission_interval_ms,
+        cfg.gcra_burst_tolerance_ms
+    );
+    Ok(())
+}
+
+fn process_propose_admin(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    new_admin: Pubkey,
+) -> ProgramResult {
+    let mut iter = accounts.iter();
+    let admin = next_account_info(&mut iter)?;
+    let gateway_account = next_account_info(&mut iter)?;
+
+    require_signer(admin)?;
+    require_writable(gateway_account)?;
+
+    let mut cfg = read_admin_gateway(program_id, admin, gateway_account)?;
+    cfg.pending_admin = new_admin;
+
+    write_gateway(gateway_account, &cfg)?;
+    if cfg.has_pending_admin() {
+        msg!("admin transfer proposed to {}", new_admin);
+    } else {
+        msg!("admin transfer cancelled");
+    }
+    Ok(())
+}
+
+fn process_accept_admin(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let mut iter = accounts.iter();
+    let new_admin = next_account_info(&mut iter)?;
+    let gateway_account = next_account_info(&mut iter)?;
+
+    require_signer(new_admin)?;
+    require_writable(gateway_account)?;
+
+    if gateway_account.owner != program_id {
+        return Err(GatewayError::InvalidAccount.into());
+    }
+
+    let mut cfg = read_gateway(gateway_account)?;
+    if !cfg.is_initialized {
+        return Err(GatewayError::InvalidAccount.into());
+    }
+    if !cfg.has_pending_admin() || cfg.pending_admin != *new_admin.key {
+        return Err(GatewayError::Unauthorized.into());
+    }
+
+    cfg.admin = cfg.pending_admin;
+    cfg.pending_admin = Pubkey::default();
+
+    write_gateway(gateway_account, &cfg)?;
+    msg!("admin transfer accepted");
+    Ok(())
+}
+
+fn process_set_backend_signers(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    signers: Vec<BackendSigner>,
+) -> ProgramResult {
+    let mut iter = accounts.iter();
+    let admin = next_account_info(&mut iter)?;
+    let gateway_account = next_account_info(&mut iter)?;
+
+    require_signer(admin)?;
+    require_writable(gateway_account)?;
+
+    if signers.len() > MAX_BACKEND_SIGNERS || signers.iter().any(BackendSigner::is_empty) {
+        return Err(GatewayError::InvalidInstruction.into());
+    }
+
+    let mut cfg = read_admin_gateway(program_id, admin, gateway_account)?;
+    cfg.backend_signers = [BackendSigner::default(); MAX_BACKEND_SIGNERS];
+    cfg.backend_signers[..signers.len()].copy_from_slice(&signers);
+
+    write_gateway(gateway_account, &cfg)?;
+    msg!("backend signers set: {}", signers.len());
+    Ok(())
+}
+
+fn process_set_paused(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    paused: bool,
+) -> ProgramResult {
+    let mut iter = accounts.iter();
+    let admin = next_account_info(&mut iter)?;
+    let gateway_account = next_account_info(&mut iter)?;
+
+    require_signer(admin)?;
+    require_writable(gateway_account)?;
+
+    let mut cfg = read_admin_gateway(program_id, admin, gateway_account)?;
+    cfg.paused = paused;
+
+    write_gateway(gateway_account, &cfg)?;
+    if paused {
+        msg!("gateway paused");
+    } else {
+        msg!("gateway resumed");
+    }
+    Ok(())
+}
+
+fn process_set_route_policy(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    route_id: u64,
+    base_price_lamports: u64,
+    max_surge_bps: u16,
+    limits: AggregateLimits,
+) -> ProgramResult {
+    let mut iter = accounts.iter();
+    let admin = next_account_info(&mut iter)?;
+    let gateway_account = next_account_info(&mut iter)?;
+    let route_account = next_account_info(&mut iter)?;
+    let system_program_account = next_account_info(&mut iter)?;
+
+    require_signer(admin)?;
+    require_writable(gateway_account)?;
+    require_writable(route_account)?;
+
+    if *system_program_account.key != system_program::ID {
+        return Err(GatewayError::InvalidAccount.into());
+    }
+
+    let mut cfg = read_admin_gateway(program_id, admin, gateway_account)?;
+
+    let (expected_route, bump) = route_policy_pda(gateway_account.key, route_id, program_id);
+    if expected_route != *route_account.key {
+        return Err(GatewayError::InvalidAccount.into());
+    }
+
+    create_pda_account(
+        admin,
+        route_account,
+        system_program_account,
+        program_id,
+        &[
+            b"route",
+            gateway_account.key.as_ref(),
+            &route_id.to_le_bytes(),
+            &[bump],
+        ],
+        RoutePolicy::LEN,
+    )?;
+
+    let mut route = read_route_policy(route_account)?;
+    if !route.is_initialized {
+        let now_ts = Clock::get()?.unix_timestamp;
+        route = RoutePolicy {
+            discriminator: RoutePolicy::DISCRIMINATOR,
+            version: RoutePolicy::VERSION,
+            is_initialized: true,
+            gateway: *gateway_account.key,
+            route_id,
+            bucket_tokens: limits.bucket_capacity,
+            bucket_last_refill_ts: now_ts,
+            quota_remaining: limits.period_limit,
+            quota_period_start_ts: now_ts,
+            bump,
+            ..RoutePolicy::default()
+        };
+        cfg.route_count = cfg.route_count.saturating_add(1);
+        write_gateway(gateway_account, &cfg)?;
+    } else {
+        let old_limits = AggregateLimits {
+            period_limit: route.period_limit,
+            period_seconds: route.period_seconds,
+            bucket_capacity: route.bucket_capacity,
+            refill_per_second: route.refill_per_second,
+        };
+        let mut usage = AggregateUsage {
+            bucket_tokens: route.bucket_tokens,
+            bucket_last_refill_ts: route.bucket_last_refill_ts,
+            quota_remaining: route.quota_remaining,
+            quota_period_start_ts: route.quota_period_start_ts,
+        };
+        rebase_aggregate_usage(
+            &old_limits,
+            &limits,
+            &mut usage,
+            Clock::get()?.unix_timestamp,
+        );
+
+        route.bucket_tokens = usage.bucket_tokens;
+        route.bucket_last_refill_ts = usage.bucket_last_refill_ts;
+        route.quota_remaining = usage.quota_remaining;
+        route.quota_period_start_ts = usage.quota_period_start_ts;
+    }
+
+    route.base_price_lamports = base_price_lamports;
+    route.max_surge_bps = max_surge_bps;
+    route.period_limit = limits.period_limit;
+    route.period_seconds = limits.period_seconds;
+    route.bucket_capacity = limits.bucket_capacity;
+    route.refill_per_second = limits.refill_per_second;
+
+    write_route_policy(route_account, &route)?;
+    msg!("route policy {} set", route_id);
+    Ok(())
+}
+
+fn process_close_route_policy(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    route_id: u64,
+) -> ProgramResult {
+    let mut iter = accounts.iter();
+    let admin = next_account_info(&mut iter)?;
+    let gateway_account = next_account_info(&mut iter)?;
+    let route_account = next_account_info(&mut iter)?;
+
+    require_signer(admin)?;
+    require_writable(admin)?;
+    require_writable(gateway_account)?;
+    require_writable(route_account)?;
+
+    let mut cfg = read_admin_gateway(program_id, admin, gateway_account)?;
+
+    if route_account.owner != program_id {
+        return Err(GatewayError::InvalidAccount.into());
+    }
+    let route = read_route_policy(route_account)?;
+    if !route.is_initialized || route.gateway != *gateway_account.key || route.route_id != route_id
+    {
+        return Err(GatewayError::InvalidAccount.into());
+    }
+
+    let balance = **route_account.lamports.borrow();
+    move_lamports(route_account, admin, balance)?;
+    route_account.try_borrow_mut_data()?.fill(0);
+
+    cfg.route_count = cfg.route_count.saturating_sub(1);
+    write_gateway(gateway_account, &cfg)?;
+
+    msg!("route policy {} closed", route_id);
+    Ok(())
+}
+
+fn process_set_plan(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    plan_id: u64,
+    rules: GatewayRules,
+) -> ProgramResult {
+    let mut iter = accounts.iter();
+    let admin = next_account_info(&mut iter)?;
+    let gateway_account = next_account_info(&mut iter)?;
+    let plan_account = next_account_info(&mut iter)?;
+    let system_program_account = next_account_info(&mut iter)?;
+
+    require_signer(admin)?;
+    require_writable(gateway_account)?;
+    require_writable(plan_account)?;
+
+    if *system_program_account.key != system_program::ID {
+        return Err(GatewayError::InvalidAccount.into());
+    }
+
+    let mut cfg = read_admin_gateway(program_id, admin, gateway_account)?;
+
+    let (expected_plan, bump) = plan_pda(gateway_account.key, plan_id, program_id);
+    if expected_plan != *plan_account.key {
+        return Err(GatewayError::InvalidAccount.into());
+    }
+
+    create_pda_account(
+        admin,
+        plan_account,
+        system_program_account,
+        program_id,
+        &[
+            b"plan",
+            gateway_account.key.as_ref(),
+            &plan_id.to_le_bytes(),
+            &[bump],
+        ],
+        Plan::LEN,
+    )?;
+
+    let mut plan = read_plan(plan_account)?;
+    if !plan.is_initialized {
+        plan = Plan {
+            discriminator: Plan::DISCRIMINATOR,
+            version: Plan::VERSION,
+            is_initialized: true,
+            gateway: *gateway_account.key,
+            plan_id,
+            bump,
+            ..Plan::default()
+        };
+        cfg.plan_count = cfg.plan_count.saturating_add(1);
+        write_gateway(gateway_account, &cfg)?;
+    }
+
+    plan.base_price_lamports = rules.base_price_lamports;
+    plan.max_surge_bps = rules.max_surge_bps;
+    plan.period_limit = rules.period_limit;
+    plan.period_seconds = rules.period_seconds;
+    plan.bucket_capacity = rules.bucket_capacity;
+    plan.refill_per_second = rules.refill_per_second;
+
+    write_plan(plan_account, &plan)?;
+    msg!("plan {} set", plan_id);
+    Ok(())
+}
+
+fn process_close_plan(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    plan_id: u64,
+) -> ProgramResult {
+    let mut iter = accounts.iter();
+    let admin = next_account_info(&mut iter)?;
+    let gateway_account = next_account_info(&mut iter)?;
+    let plan_account = next_account_info(&mut iter)?;
+
+    require_signer(admin)?;
+    require_writable(admin)?;
+    require_writable(gateway_account)?;
+    require_writable(plan_account)?;
+
+    let mut cfg = read_admin_gateway(program_id, admin, gateway_account)?;
+
+    let plan = read_gateway_plan(program_id, gateway_account.key, plan_account)?;
+    if plan.plan_id != plan_id {
+        return Err(GatewayError::InvalidAccount.into());
+    }
+    if plan.consumer_count > 0 {
+        msg!(
+            "{} consumers still on plan {}",
+            plan.consumer_count,
+            plan_id
+        );
+        return Err(GatewayError::ConsumersOutstanding.into());
+    }
+
+    let balance = **plan_account.lamports.borrow();
+    move_lamports(plan_account, admin, balance)?;
+    plan_account.try_borrow_mut_data()?.fill(0);
+
+    cfg.plan_count = cfg.plan_count.saturating_sub(1);
+    write_gateway(gateway_account, &cfg)?;
+
+    msg!("plan {} closed", plan_id);
+    Ok(())
+}
+
+fn process_change_plan(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    plan_id: Option<u64>,
+) -> ProgramResult {
+    let mut iter = accounts.iter();
+    let admin = next_account_info(&mut iter)?;
+    let gateway_account = next_account_info(&mut iter)?;
+    let consumer_account = next_account_info(&mut iter)?;
+
+    require_signer(admin)?;
+    require_writable(consumer_account)?;
+
+    let gateway = read_admin_gateway(program_id, admin, gateway_account)?;
+
+    if consumer_account.owner != program_id {
+        return Err(GatewayError::InvalidAccount.into());
+    }
+    let mut consumer = read_consumer(consumer_account)?;
+    if !consumer.is_initialized || consumer.gateway != *gateway_account.key {
+        return Err(GatewayError::InvalidAccount.into());
+    }
+
+    let current_plan = if consumer.has_plan() {
+        let plan_account = next_account_info(&mut iter)?;
+        require_writable(plan_account)?;
+        if *plan_account.key != consumer.plan {
+            return Err(GatewayError::InvalidAccount.into());
+        }
+        let plan = read_gateway_plan(program_id, gateway_account.key, plan_account)?;
+        Some((plan_account, plan))
+    } else {
+        None
+    };
+
+    let new_plan = match plan_id {
+        Some(plan_id) => {
+            let plan_account = next_account_info(&mut iter)?;
+            require_writable(plan_account)?;
+            let plan = read_gateway_plan(program_id, gateway_account.key, plan_account)?;
+            if plan.plan_id != plan_id {
+                return Err(GatewayError::InvalidAccount.into());
+            }
+            Some((plan_account, plan))
+        }
+        None => None,
+    };
+
+    let new_plan_key = new_plan
+        .as_ref()
+        .map_or(Pubkey::default(), |(account, _)| *account.key);
+    if new_plan_key == consumer.plan {
+        msg!("consumer already on requested plan");
+        return Ok(());
+    }
+
+    let old_rules = current_plan.as_ref().map_or_else(
+        || gateway_rules(&gateway),
+        |(_, plan)| plan_rules(plan, &gateway),
+    );
+    let new_rules = new_plan.as_ref().map_or_else(
+        || gateway_rules(&gateway),
+        |(_, plan)| plan_rules(plan, &gateway),
+    );
+
+    let mut runtime = runtime_state(&consumer);
+    rebase_runtime_state(
+        &old_rules,
+        &new_rules,
+        &mut runtime,
+        Clock::get()?.unix_timestamp,
+    );
+    store_runtime_state(&mut consumer, &runtime);
+    consumer.plan = new_plan_key;
+    write_consumer(consumer_account, &consumer)?;
+
+    if let Some((plan_account, mut plan)) = current_plan {
+        plan.consumer_count = plan.consumer_count.saturating_sub(1);
+        write_plan(plan_account, &plan)?;
+    }
+    if let Some((plan_account, mut plan)) = new_plan {
+        plan.consumer_count = plan
+            .consumer_count
+            .checked_add(1)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        write_plan(plan_account, &plan)?;
+    }
+
+    match plan_id {
+        Some(plan_id) => msg!("consumer moved to plan {}", plan_id),
+        None => msg!("consumer moved to gateway rules"),
+    }
+    Ok(())
+}
+
+fn process_migrate_account(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let mut iter = accounts.iter();
+    let payer = next_account_info(&mut iter)?;
+    let target = next_account_info(&mut iter)?;
+    let system_program_account = next_account_info(&mut iter)?;
+
+    require_signer(payer)?;
+    require_writable(payer)?;
+    require_writable(target)?;
+
+    if *system_program_account.key != system_program::ID {
+        return Err(GatewayError::InvalidAccount.into());
+    }
+    if target.owner != program_id {
+        return Err(GatewayError::InvalidAccount.into());
+    }
+
+    let upgraded = {
+        let data = target.try_borrow_data()?;
+        if stored_layout::<GatewayConfig>(&data) == StoredLayout::Current
+            || stored_layout::<ConsumerAccount>(&data) == StoredLayout::Current
+            || stored_layout::<RoutePolicy>(&data) == StoredLayout::Current
+            || stored_layout::<Plan>(&data) == StoredLayout::Current
+            || stored_layout::<GatewayStats>(&data) == StoredLayout::Current
+        {
+            msg!("account already at current layout");
+            return Ok(());
+        }
+        upgrade_layout::<GatewayConfig>(&data)
+            .or_else(|| upgrade_layout::<ConsumerAccount>(&data))
+            .or_else(|| upgrade_layout::<RoutePolicy>(&data))
+            .or_else(|| upgrade_layout::<Plan>(&data))
+            .or_else(|| upgrade_layout::<GatewayStats>(&data))
+            .ok_or(GatewayError::InvalidAccount)?
+    };
+    if target.data_len() == ConsumerAccount::BASELINE_LEN {
+        let consumer = ConsumerAccount::try_from_slice(&upgraded)
+            .map_err(|_| ProgramError::InvalidAccountData)?;
+        let gateway_account = next_account_info(&mut iter)?;
+        count_migrated_consumer(program_id, &consumer, gateway_account)?;
+    }
+
+    // Fund only the extra rent so prepaid consumer balances are left untouched.
+    let rent = Rent::get()?;
+    let extra_rent = rent
+        .minimum_balance(upgraded.len())
+        .saturating_sub(rent.minimum_balance(target.data_len()));
+    if extra_rent > 0 {
+        invoke(
+            &system_instruction::transfer(payer.key, target.key, extra_rent),
+            &[
+                payer.clone(),
+                target.clone(),
+                system_program_account.clone(),
+            ],
+        )?;
+    }
+
+    target.realloc(upgraded.len(), false)?;
+    target.try_borrow_mut_data()?.copy_from_slice(&upgraded);
+
+    msg!("account migrated to {} bytes", upgraded.len());
+    Ok(())
+}
+
+/// Baseline gateways did not count their consumers, so each baseline consumer
+/// adds itself to its gateway's count as it is migrated. The gateway has to be
+/// migrated first. A closed gateway is skipped so the consumer can still close.
+fn count_migrated_consumer(
+    program_id: &Pubkey,
+    consumer: &ConsumerAccount,
+    gateway_account: &AccountInfo,
+) -> ProgramResult {
+    if consumer.gateway != *gateway_account.key {
+        return Err(GatewayError::InvalidAccount.into());
+    }
+    if gateway_account.owner != program_id {
+        return Ok(());
+    }
+
+    require_writable(gateway_account)?;
+    let mut gateway = read_gateway(gateway_account)?;
+    if !gateway.is_initialized {
+        return Ok(());
+    }
+    gateway.consumer_count = gateway.consumer_count.saturating_add(1);
+    write_gateway(gateway_account, &gateway)
+}
+
+fn create_pda_account<'a>(
+    payer: &AccountInfo<'a>,
+    pda: &AccountInfo<'a>,
+    system_program_account: &AccountInfo<'a>,
+    program_id: &Pubkey,
+    signer_seeds: &[&[u8]],
+    data_len: usize,
+) -> ProgramResult {
+    if pda.owner == program_id && pda.data_len() == data_len {
+        return Ok(());
+    }
+
+    if pda.owner != &system_program::ID {
+        return Err(GatewayError::InvalidAccount.into());
+    }
+
+    let rent = Rent::get()?;
+    let lamports = rent.minimum_balance(data_len);
+
+    invoke_signed(
+        &system_instruction::create_account(
+            payer.key,
+            pda.key,
+            lamports,
+            data_len as u64,
+            program_id,
+        ),
+        &[payer.clone(), pda.clone(), system_program_account.clone()],
+        &[signer_seeds],
+    )
+}
+
+fn read_gateway(account: &AccountInfo) -> Result<GatewayConfig, ProgramError> {
+    read_versioned(account)
+}
+
+fn read_admin_gateway(
+    program_id: &Pubkey,
+    admin: &AccountInfo,
+    gateway_account: &AccountInfo,
+) -> Result<GatewayConfig, ProgramError> {
+    if gateway_account.owner != program_id {
+        return Err(GatewayError::InvalidAccount.into());
+    }
+
+    let cfg = read_gateway(gateway_account)?;
+    if !cfg.is_initialized {
+        return Err(GatewayError::InvalidAccount.into());
+    }
+    if cfg.admin != *admin.key {
+        return Err(GatewayError::Unauthorized.into());
+    }
+    Ok(cfg)
+}
+
+/// Decodes a versioned account. A fully zeroed account of the right size is
+/// decoded as-is (`is_initialized == false`) so creation paths can detect it.
+fn read_versioned<T: VersionedLayout + BorshDeserialize>(
+    account: &AccountInfo,
+) -> Result<T, ProgramError> {
+    let data = account.try_borrow_data()?;
+    match stored_layout::<T>(&data) {
+        StoredLayout::Current => {}
+        StoredLayout::Outdated => return Err(GatewayError::AccountNeedsMigration.into()),
+        StoredLayout::Unrecognized
+            if data.len() == T::LEN && data.iter().all(|byte| *byte == 0) => {}
+        StoredLayout::Unrecognized => return Err(GatewayError::InvalidAccount.into()),
+    }
+
+    T::try_from_slice(&data).map_err(|_| ProgramError::InvalidAccountData)
+}
+
+fn write_gateway(account: &AccountInfo, cfg: &GatewayConfig) -> ProgramResult {
+    let mut data = account.try_borrow_mut_data()?;
+    cfg.serialize(&mut &mut data[..])
+        .map_err(|_| ProgramError::InvalidAccountData)
+}
+
+fn read_owned_consumer(
+    program_id: &Pubkey,
+    owner: &AccountInfo,
+    consumer_account: &AccountInfo,
+) -> Result<ConsumerAccount, ProgramError> {
+    if consumer_account.owner != program_id {
+        return Err(GatewayError::InvalidAccount.into());
+    }
+
+    let consumer = read_consumer(consumer_account)?;
+    if !consumer.is_initialized || consumer.owner != *owner.key {
+        return Err(GatewayError::Unauthorized.into());
+    }
+    Ok(consumer)
+}
+
+fn read_consumer(account: &AccountInfo) -> Result<ConsumerAccount, ProgramError> {
+    read_versioned(account)
+}
+
+fn write_consumer(account: &AccountInfo, consumer: &ConsumerAccount) -> ProgramResult {
+    let mut data = account.try_borrow_mut_data()?;
+    consumer
+        .serialize(&mut &mut data[..])
+        .map_err(|_| ProgramError::InvalidAccountData)
+}
+
+/// Moves lamports out of an account owned by this program without a system CPI.
+fn move_lamports(source: &AccountInfo, dest: &AccountInfo, lamports: u64) -> ProgramResult {
+    {
+        let mut source = source.try_borrow_mut_lamports()?;
+        if **source < lamports {
+            return Err(GatewayError::InsufficientBalance.into());
+        }
+        **source -= lamports;
+    }
+
+    let mut dest = dest.try_borrow_mut_lamports()?;
+    **dest = (**dest)
+        .checked_add(lamports)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    Ok(())
+}
+
+/// Token or Token-2022 account owned by `token_program`.
+fn read_token_account(
+    account: &AccountInfo,
+    token_program: &Pubkey,
+) -> Result<TokenAccount, ProgramError> {
+    if account.owner != token_program || check_spl_token_program_account(token_program).is_err() {
+        return Err(GatewayError::InvalidAccount.into());
+    }
+    Ok(StateWithExtensions::<TokenAccount>::unpack(&account.try_borrow_data()?)?.base)
+}
+
+fn read_mint(account: &AccountInfo, token_program: &Pubkey) -> Result<Mint, ProgramError> {
+    if account.owner != token_program || check_spl_token_program_account(token_program).is_err() {
+        return Err(GatewayError::InvalidAccount.into());
+    }
+    Ok(StateWithExtensions::<Mint>::unpack(&account.try_borrow_data()?)?.base)
+}
+
+/// `transfer_checked` of billing tokens; `signer_seeds` is empty when
+/// `authority` signed the transaction itself. Returns the transfer fee the
+/// mint withholds from `destination`.
+fn transfer_tokens<'a>(
+    authority: &AccountInfo<'a>,
+    source: &AccountInfo<'a>,
+    mint: &AccountInfo<'a>,
+    destination: &AccountInfo<'a>,
+    token_program: &AccountInfo<'a>,
+    amount: u64,
+    signer_seeds: &[&[&[u8]]],
+) -> Result<u64, ProgramError> {
+    let decimals = read_mint(mint, token_program.key)?.decimals;
+    let withheld = mint_transfer_fee(&mint.try_borrow_data()?, Clock::get()?.epoch, amount)?;
+    invoke_signed(
+        &spl_token_2022::instruction::transfer_checked(
+            token_program.key,
+            source.key,
+            mint.key,
+            destination.key,
+            authority.key,
+            &[],
+            amount,
+            decimals,
+        )?,
+        &[
+            source.clone(),
+            mint.clone(),
+            destination.clone(),
+            authority.clone(),
+            token_program.clone(),
+        ],
+        signer_seeds,
+    )?;
+    if withheld > 0 {
+        msg!("{} tokens withheld as transfer fee", withheld);
+    }
+    Ok(withheld)
+}
+
+/// Moves tokens out of a consumer's vault, signed by the consumer PDA.
+fn transfer_from_vault<'a>(
+    consumer_account: &AccountInfo<'a>,
+    consumer: &ConsumerAccount,
+    vault: &AccountInfo<'a>,
+    mint: &AccountInfo<'a>,
+    destination: &AccountInfo<'a>,
+    token_program: &AccountInfo<'a>,
+    amount: u64,
+) -> Result<u64, ProgramError> {
+    let api_key_id = consumer.api_key_id.to_le_bytes();
+    let bump = [consumer.bump];
+    transfer_tokens(
+        consumer_account,
+        vault,
+        mint,
+        destination,
+        token_program,
+        amount,
+        &[&[
+            b"consumer",
+            consumer.gateway.as_ref(),
+            consumer.owner.as_ref(),
+            &api_key_id,
+            &bump,
+        ]],
+    )
+}
+
+fn read_route_policy(account: &AccountInfo) -> Result<RoutePolicy, ProgramError> {
+    read_versioned(account)
+}
+
+fn write_route_policy(account: &AccountInfo, route: &RoutePolicy) -> ProgramResult {
+    let mut data = account.try_borrow_mut_data()?;
+    route
+        .serialize(&mut &mut data[..])
+        .map_err(|_| ProgramError::InvalidAccountData)
+}
+
+fn read_gateway_stats(account: &AccountInfo) -> Result<GatewayStats, ProgramError> {
+    read_versioned(account)
+}
+
+fn write_gateway_stats(account: &AccountInfo, stats: &GatewayStats) -> ProgramResult {
+    let mut data = account.try_borrow_mut_data()?;
+    stats
+        .serialize(&mut &mut data[..])
+        .map_err(|_| ProgramError::InvalidAccountData)
+}
+
+fn read_plan(account: &AccountInfo) -> Result<Plan, ProgramError> {
+    read_versioned(account)
+}
+
+/// Reads an initialized plan that belongs to `gateway_key`.
+fn read_gateway_plan(
+    program_id: &Pubkey,
+    gateway_key: &Pubkey,
+    account: &AccountInfo,
+) -> Result<Plan, ProgramError> {
+    if account.owner != program_id {
+        return Err(GatewayError::InvalidAccount.into());
+    }
+
+    let plan = read_plan(account)?;
+    if !plan.is_initialized || plan.gateway != *gateway_key {
+        return Err(GatewayError::InvalidAccount.into());
+    }
+    Ok(plan)
+}
+
+fn write_plan(account: &AccountInfo, plan: &Plan) -> ProgramResult {
+    let mut data = account.try_borrow_mut_data()?;
+    plan.serialize(&mut &mut data[..])
         .map_err(|_| ProgramError::InvalidAccountData)
 }
 
@@ -385,6 +2693,8 @@ fn map_consume_error(err: ConsumeError) -> ProgramError {
         ConsumeError::RateLimited => GatewayError::RateLimited.into(),
         ConsumeError::QuotaExceeded => GatewayError::QuotaExceeded.into(),
         ConsumeError::InsufficientBalance => GatewayError::InsufficientBalance.into(),
+        ConsumeError::InvalidUnits => GatewayError::InvalidInstruction.into(),
+        ConsumeError::ChargeOverflow => ProgramError::ArithmeticOverflow,
     }
 }
 
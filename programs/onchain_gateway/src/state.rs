@@ -1,32 +1,250 @@
 use borsh::{BorshDeserialize, BorshSerialize};
-use solana_program::pubkey::Pubkey;
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+use spl_token_2022::{
+    extension::{
+        transfer_fee::{TransferFeeAmount, TransferFeeConfig},
+        BaseStateWithExtensions, StateWithExtensions,
+    },
+    state::{Account as TokenAccount, Mint},
+};
 
-#[derive(Debug, Clone, BorshSerialize, BorshDeserialize, PartialEq, Eq)]
+use crate::{
+    layout::{VersionedLayout, HEADER_LEN},
+    pricing::{PricingModel, VolumeDiscountTier, MAX_VOLUME_DISCOUNT_TIERS},
+};
+
+pub const MAX_BACKEND_SIGNERS: usize = 4;
+
+/// One slot of the backend signer allowlist. An empty slot holds `Pubkey::default()`.
+#[derive(Debug, Clone, Copy, Default, BorshSerialize, BorshDeserialize, PartialEq, Eq)]
+pub struct BackendSigner {
+    pub signer: Pubkey,
+    /// Unix timestamp after which the signer is rejected; `0` means no expiry.
+    pub expires_at_ts: i64,
+}
+
+impl BackendSigner {
+    pub const LEN: usize = 32 + 8;
+
+    pub fn is_empty(&self) -> bool {
+        self.signer == Pubkey::default()
+    }
+
+    pub fn is_active(&self, now_ts: i64) -> bool {
+        !self.is_empty() && (self.expires_at_ts == 0 || now_ts < self.expires_at_ts)
+    }
+}
+
+/// How the period quota is counted.
+#[derive(Debug, Clone, Copy, Default, BorshSerialize, BorshDeserialize, PartialEq, Eq)]
+pub enum QuotaMode {
+    /// Full reset every `period_seconds` (allows up to 2x bursts across a boundary).
+    #[default]
+    FixedWindow,
+    /// Sliding-window counter: the previous window's usage counts against the
+    /// limit until the grid moves past it, so no trailing period exceeds it.
+    SlidingWindow,
+}
+
+#[derive(Debug, Clone, Default, BorshSerialize, BorshDeserialize, PartialEq, Eq)]
 pub struct GatewayConfig {
+    pub discriminator: [u8; 8],
+    pub version: u8,
     pub is_initialized: bool,
     pub admin: Pubkey,
+    /// Admin proposed via `ProposeAdmin`; `Pubkey::default()` when no transfer is pending.
+    pub pending_admin: Pubkey,
+    /// Key the gateway PDA was derived from. Fixed at creation so the address
+    /// does not depend on whoever currently holds `admin`.
+    pub creator: Pubkey,
     pub treasury: Pubkey,
-    pub backend_signer: Pubkey,
+    pub backend_signers: [BackendSigner; MAX_BACKEND_SIGNERS],
     pub base_price_lamports: u64,
     pub max_surge_bps: u16,
     pub period_limit: u64,
     pub period_seconds: i64,
     pub bucket_capacity: u64,
     pub refill_per_second: u64,
+    /// Circuit breaker: while set, `Consume` and `RegisterConsumer` are rejected.
+    pub paused: bool,
+    /// Consumers registered and not yet closed; `CloseGateway` requires zero.
+    pub consumer_count: u64,
     pub bump: u8,
+    /// Curve applied to the base price as a consumer's quota is used up.
+    pub pricing: PricingModel,
+    /// Lifetime discounts by `total_calls`, applied after surge; unused slots are zeroed.
+    pub volume_discounts: [VolumeDiscountTier; MAX_VOLUME_DISCOUNT_TIERS],
+    /// Fractional refill: milli-tokens added per `refill_interval_seconds`.
+    /// `0` keeps the whole-token `refill_per_second`.
+    pub refill_milli_tokens: u64,
+    pub refill_interval_seconds: i64,
+    pub quota_mode: QuotaMode,
+    /// GCRA limiter parameters; a non-zero emission interval replaces the token bucket.
+    pub gcra_emission_interval_ms: u64,
+    pub gcra_burst_tolerance_ms: u64,
+    /// `GatewayStats` holding the gateway-wide capacity; default when there is none.
+    pub stats: Pubkey,
+    /// Free units per `period_seconds`, then a lifetime trial, before charging starts.
+    pub free_calls_per_period: u64,
+    pub trial_calls: u64,
+    /// SPL mint balances and charges are kept in; default for native lamports.
+    /// In token mode `treasury` is a token account of this mint.
+    pub billing_mint: Pubkey,
+    pub billing_decimals: u8,
+    /// `Plan` and `RoutePolicy` accounts not yet closed; `CloseGateway` requires zero.
+    pub plan_count: u64,
+    pub route_count: u64,
+}
+
+/// `GatewayConfig` as first deployed: one backend signer, and no admin transfer,
+/// creator, pause flag or consumer count.
+#[derive(BorshDeserialize)]
+struct BaselineGatewayConfig {
+    is_initialized: bool,
+    admin: Pubkey,
+    treasury: Pubkey,
+    backend_signer: Pubkey,
+    base_price_lamports: u64,
+    max_surge_bps: u16,
+    period_limit: u64,
+    period_seconds: i64,
+    bucket_capacity: u64,
+    refill_per_second: u64,
+    bump: u8,
 }
 
 impl GatewayConfig {
-    pub const LEN: usize = 1 + 32 + 32 + 32 + 8 + 2 + 8 + 8 + 8 + 8 + 1;
+    /// Size of the originally deployed layout, see `BaselineGatewayConfig`.
+    pub const BASELINE_LEN: usize = 1 + 32 + 32 + 32 + 8 + 2 + 8 + 8 + 8 + 8 + 1;
+    pub const LEN: usize = HEADER_LEN
+        + 1
+        + 32
+        + 32
+        + 32
+        + 32
+        + BackendSigner::LEN * MAX_BACKEND_SIGNERS
+        + 8
+        + 2
+        + 8
+        + 8
+        + 8
+        + 8
+        + 1
+        + 8
+        + 1
+        + PricingModel::LEN
+        + VolumeDiscountTier::LEN * MAX_VOLUME_DISCOUNT_TIERS
+        + 8
+        + 8
+        + 1
+        + 8
+        + 8
+        + 32
+        + 8
+        + 8
+        + 32
+        + 1
+        + 8
+        + 8;
+
+    pub fn has_pending_admin(&self) -> bool {
+        self.pending_admin != Pubkey::default()
+    }
+
+    pub fn has_stats(&self) -> bool {
+        self.stats != Pubkey::default()
+    }
+
+    pub fn has_token_billing(&self) -> bool {
+        self.billing_mint != Pubkey::default()
+    }
+
+    pub fn is_backend_signer(&self, key: &Pubkey, now_ts: i64) -> bool {
+        self.backend_signers
+            .iter()
+            .any(|entry| entry.signer == *key && entry.is_active(now_ts))
+    }
+}
+
+impl VersionedLayout for GatewayConfig {
+    const DISCRIMINATOR: [u8; 8] = *b"SGGATEWY";
+    const VERSION: u8 = 10;
+    const LEN: usize = Self::LEN;
+    const BASELINE_LEN: Option<usize> = Some(Self::BASELINE_LEN);
+
+    fn len_for_version(version: u8) -> Option<usize> {
+        match version {
+            10 => Some(Self::LEN),
+            _ => None,
+        }
+    }
+
+    /// `consumer_count` starts at zero; each baseline consumer adds itself
+    /// when it is migrated.
+    fn upgrade_baseline(data: &[u8]) -> Option<Vec<u8>> {
+        let old = BaselineGatewayConfig::try_from_slice(data).ok()?;
+        let mut backend_signers = [BackendSigner::default(); MAX_BACKEND_SIGNERS];
+        backend_signers[0].signer = old.backend_signer;
+        borsh::to_vec(&GatewayConfig {
+            discriminator: Self::DISCRIMINATOR,
+            version: Self::VERSION,
+            is_initialized: old.is_initialized,
+            admin: old.admin,
+            // Baseline gateways were derived from the admin key.
+            creator: old.admin,
+            treasury: old.treasury,
+            backend_signers,
+            base_price_lamports: old.base_price_lamports,
+            max_surge_bps: old.max_surge_bps,
+            period_limit: old.period_limit,
+            period_seconds: old.period_seconds,
+            bucket_capacity: old.bucket_capacity,
+            refill_per_second: old.refill_per_second,
+            bump: old.bump,
+            ..GatewayConfig::default()
+        })
+        .ok()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, BorshSerialize, BorshDeserialize, PartialEq, Eq)]
+pub enum ConsumerStatus {
+    #[default]
+    Active,
+    /// Blocked by the admin; can be lifted with `ReinstateConsumer`.
+    Suspended,
+    /// Permanently blocked; cannot be reinstated.
+    Revoked,
+}
+
+/// Terms negotiated for a single consumer, set by the admin. A zero field
+/// leaves the plan or gateway value in place.
+#[derive(Debug, Clone, Copy, Default, BorshSerialize, BorshDeserialize, PartialEq, Eq)]
+pub struct ConsumerOverrides {
+    /// Discount on the unit price, in basis points (at most 10_000).
+    pub discount_bps: u16,
+    pub bucket_capacity: u64,
+    pub refill_per_second: u64,
+    pub period_limit: u64,
+}
+
+impl ConsumerOverrides {
+    pub const LEN: usize = 2 + 8 + 8 + 8;
 }
 
-#[derive(Debug, Clone, BorshSerialize, BorshDeserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Default, BorshSerialize, BorshDeserialize, PartialEq, Eq)]
 pub struct ConsumerAccount {
+    pub discriminator: [u8; 8],
+    pub version: u8,
     pub is_initialized: bool,
     pub gateway: Pubkey,
     pub owner: Pubkey,
     pub api_key_id: u64,
     pub api_key_hash: [u8; 32],
+    /// Hash replaced by the last `RotateApiKey`, still accepted until `previous_key_expires_ts`.
+    pub previous_api_key_hash: [u8; 32],
+    pub previous_key_expires_ts: i64,
+    pub status: ConsumerStatus,
     pub bucket_tokens: u64,
     pub bucket_last_refill_ts: i64,
     pub quota_remaining: u64,
@@ -34,14 +252,279 @@ pub struct ConsumerAccount {
     pub total_calls: u64,
     pub total_spent_lamports: u64,
     pub bump: u8,
+    /// Assigned `Plan`; `Pubkey::default()` means the gateway's own rules apply.
+    pub plan: Pubkey,
+    pub overrides: ConsumerOverrides,
+    /// Milli-tokens held besides `bucket_tokens` when fractional refill is used.
+    pub bucket_milli_remainder: u64,
+    /// Sliding-window counters for the window before and the window starting
+    /// at `quota_period_start_ts`.
+    pub window_previous_used: u64,
+    pub window_current_used: u64,
+    /// GCRA theoretical arrival time, in milliseconds since the Unix epoch.
+    pub gcra_tat_ms: i64,
+    /// Free units used since `free_period_start_ts`, and trial units used ever.
+    pub free_calls_used: u64,
+    pub free_period_start_ts: i64,
+    pub trial_calls_used: u64,
+    /// Admin-granted credit line; charges beyond the prepaid balance accrue as
+    /// `debt_lamports` up to this limit.
+    pub credit_limit_lamports: u64,
+    pub debt_lamports: u64,
+    /// Token account holding the prepaid balance when the gateway bills in an
+    /// SPL mint; its authority is this consumer PDA.
+    pub token_vault: Pubkey,
+    /// Period limit `quota_remaining` was counted against, so a changed limit
+    /// can carry the units already used over.
+    pub quota_period_limit: u64,
+}
+
+/// `ConsumerAccount` as first deployed: no previous key hash or status.
+#[derive(BorshDeserialize)]
+struct BaselineConsumerAccount {
+    is_initialized: bool,
+    gateway: Pubkey,
+    owner: Pubkey,
+    api_key_id: u64,
+    api_key_hash: [u8; 32],
+    bucket_tokens: u64,
+    bucket_last_refill_ts: i64,
+    quota_remaining: u64,
+    quota_period_start_ts: i64,
+    total_calls: u64,
+    total_spent_lamports: u64,
+    bump: u8,
 }
 
 impl ConsumerAccount {
-    pub const LEN: usize = 1 + 32 + 32 + 8 + 32 + 8 + 8 + 8 + 8 + 8 + 8 + 1;
+    /// Size of the originally deployed layout, see `BaselineConsumerAccount`.
+    pub const BASELINE_LEN: usize = 1 + 32 + 32 + 8 + 32 + 8 + 8 + 8 + 8 + 8 + 8 + 1;
+    pub const LEN: usize = HEADER_LEN
+        + 1
+        + 32
+        + 32
+        + 8
+        + 32
+        + 32
+        + 8
+        + 1
+        + 8
+        + 8
+        + 8
+        + 8
+        + 8
+        + 8
+        + 1
+        + 32
+        + ConsumerOverrides::LEN
+        + 8
+        + 8
+        + 8
+        + 8
+        + 8
+        + 8
+        + 8
+        + 8
+        + 8
+        + 32
+        + 8;
+
+    pub fn has_plan(&self) -> bool {
+        self.plan != Pubkey::default()
+    }
+
+    pub fn has_token_vault(&self) -> bool {
+        self.token_vault != Pubkey::default()
+    }
+
+    /// Credit still available on top of the prepaid balance.
+    pub fn credit_headroom(&self) -> u64 {
+        self.credit_limit_lamports
+            .saturating_sub(self.debt_lamports)
+    }
+
+    pub fn accepts_api_key(&self, presented_hash: &[u8; 32], now_ts: i64) -> bool {
+        if self.api_key_hash == *presented_hash {
+            return true;
+        }
+        self.previous_api_key_hash == *presented_hash && now_ts < self.previous_key_expires_ts
+    }
+}
+
+impl VersionedLayout for ConsumerAccount {
+    const DISCRIMINATOR: [u8; 8] = *b"SGCONSMR";
+    const VERSION: u8 = 10;
+    const LEN: usize = Self::LEN;
+    const BASELINE_LEN: Option<usize> = Some(Self::BASELINE_LEN);
+
+    fn len_for_version(version: u8) -> Option<usize> {
+        match version {
+            10 => Some(Self::LEN),
+            _ => None,
+        }
+    }
+
+    fn upgrade_baseline(data: &[u8]) -> Option<Vec<u8>> {
+        let old = BaselineConsumerAccount::try_from_slice(data).ok()?;
+        borsh::to_vec(&ConsumerAccount {
+            discriminator: Self::DISCRIMINATOR,
+            version: Self::VERSION,
+            is_initialized: old.is_initialized,
+            gateway: old.gateway,
+            owner: old.owner,
+            api_key_id: old.api_key_id,
+            api_key_hash: old.api_key_hash,
+            status: ConsumerStatus::Active,
+            bucket_tokens: old.bucket_tokens,
+            bucket_last_refill_ts: old.bucket_last_refill_ts,
+            quota_remaining: old.quota_remaining,
+            quota_period_start_ts: old.quota_period_start_ts,
+            total_calls: old.total_calls,
+            total_spent_lamports: old.total_spent_lamports,
+            bump: old.bump,
+            ..ConsumerAccount::default()
+        })
+        .ok()
+    }
+}
+
+/// Per-route price and capacity, registered by the gateway admin. The bucket
+/// and quota counters are shared by every consumer calling the route.
+#[derive(Debug, Clone, Default, BorshSerialize, BorshDeserialize, PartialEq, Eq)]
+pub struct RoutePolicy {
+    pub discriminator: [u8; 8],
+    pub version: u8,
+    pub is_initialized: bool,
+    pub gateway: Pubkey,
+    pub route_id: u64,
+    pub base_price_lamports: u64,
+    pub max_surge_bps: u16,
+    pub period_limit: u64,
+    pub period_seconds: i64,
+    pub bucket_capacity: u64,
+    pub refill_per_second: u64,
+    pub bucket_tokens: u64,
+    pub bucket_last_refill_ts: i64,
+    pub quota_remaining: u64,
+    pub quota_period_start_ts: i64,
+    pub bump: u8,
 }
 
-pub fn gateway_pda(admin: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
-    Pubkey::find_program_address(&[b"gateway", admin.as_ref()], program_id)
+impl RoutePolicy {
+    pub const LEN: usize = HEADER_LEN + 1 + 32 + 8 + 8 + 2 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 1;
+}
+
+impl VersionedLayout for RoutePolicy {
+    const DISCRIMINATOR: [u8; 8] = *b"SGROUTE_";
+    const VERSION: u8 = 1;
+    const LEN: usize = Self::LEN;
+
+    fn len_for_version(version: u8) -> Option<usize> {
+        match version {
+            1 => Some(Self::LEN),
+            _ => None,
+        }
+    }
+}
+
+/// Gateway-wide capacity shared by every consumer, protecting the upstream
+/// service. Checked before any per-consumer limit.
+#[derive(Debug, Clone, Default, BorshSerialize, BorshDeserialize, PartialEq, Eq)]
+pub struct GatewayStats {
+    pub discriminator: [u8; 8],
+    pub version: u8,
+    pub is_initialized: bool,
+    pub gateway: Pubkey,
+    pub period_limit: u64,
+    pub period_seconds: i64,
+    pub bucket_capacity: u64,
+    pub refill_per_second: u64,
+    pub bucket_tokens: u64,
+    pub bucket_last_refill_ts: i64,
+    pub quota_remaining: u64,
+    pub quota_period_start_ts: i64,
+    pub bump: u8,
+    /// Congestion pricing: when `congestion_target_calls > 0`, price surge follows
+    /// gateway-wide units over the trailing `congestion_window_seconds`.
+    pub congestion_window_seconds: i64,
+    pub congestion_target_calls: u64,
+    pub congestion_window_start_ts: i64,
+    pub congestion_previous_calls: u64,
+    pub congestion_current_calls: u64,
+}
+
+impl GatewayStats {
+    pub const V1_LEN: usize = HEADER_LEN + 1 + 32 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 1;
+    pub const LEN: usize = Self::V1_LEN + 8 + 8 + 8 + 8 + 8;
+
+    pub fn has_congestion_pricing(&self) -> bool {
+        self.congestion_target_calls > 0
+    }
+}
+
+impl VersionedLayout for GatewayStats {
+    const DISCRIMINATOR: [u8; 8] = *b"SGSTATS_";
+    const VERSION: u8 = 2;
+    const LEN: usize = Self::LEN;
+
+    fn len_for_version(version: u8) -> Option<usize> {
+        match version {
+            1 => Some(Self::V1_LEN),
+            2 => Some(Self::LEN),
+            _ => None,
+        }
+    }
+}
+
+/// Subscription tier registered by the gateway admin. Consumers assigned to a
+/// plan are limited and priced by its rules instead of the gateway defaults.
+#[derive(Debug, Clone, Default, BorshSerialize, BorshDeserialize, PartialEq, Eq)]
+pub struct Plan {
+    pub discriminator: [u8; 8],
+    pub version: u8,
+    pub is_initialized: bool,
+    pub gateway: Pubkey,
+    pub plan_id: u64,
+    pub base_price_lamports: u64,
+    pub max_surge_bps: u16,
+    pub period_limit: u64,
+    pub period_seconds: i64,
+    pub bucket_capacity: u64,
+    pub refill_per_second: u64,
+    /// Consumers currently assigned; `ClosePlan` requires zero.
+    pub consumer_count: u64,
+    pub bump: u8,
+    /// Fractional refill, as on `GatewayConfig`.
+    pub refill_milli_tokens: u64,
+    pub refill_interval_seconds: i64,
+    /// Free allowance, as on `GatewayConfig`.
+    pub free_calls_per_period: u64,
+    pub trial_calls: u64,
+}
+
+impl Plan {
+    pub const V1_LEN: usize = HEADER_LEN + 1 + 32 + 8 + 8 + 2 + 8 + 8 + 8 + 8 + 8 + 1;
+    pub const V2_LEN: usize = Self::V1_LEN + 8 + 8;
+    pub const LEN: usize = Self::V2_LEN + 8 + 8;
+}
+
+impl VersionedLayout for Plan {
+    const DISCRIMINATOR: [u8; 8] = *b"SGPLAN__";
+    const VERSION: u8 = 3;
+    const LEN: usize = Self::LEN;
+
+    fn len_for_version(version: u8) -> Option<usize> {
+        match version {
+            1 => Some(Self::V1_LEN),
+            2 => Some(Self::V2_LEN),
+            3 => Some(Self::LEN),
+            _ => None,
+        }
+    }
+}
+
+pub fn gateway_pda(creator: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"gateway", creator.as_ref()], program_id)
 }
 
 pub fn consumer_pda(
@@ -60,3 +543,47 @@ pub fn consumer_pda(
         program_id,
     )
 }
+
+pub fn route_policy_pda(gateway: &Pubkey, route_id: u64, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[b"route", gateway.as_ref(), &route_id.to_le_bytes()],
+        program_id,
+    )
+}
+
+pub fn consumer_vault_pda(consumer: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"vault", consumer.as_ref()], program_id)
+}
+
+/// Part of `amount` a Token-2022 transfer-fee mint withholds at the
+/// destination in `epoch`; `0` for mints without the extension.
+pub fn mint_transfer_fee(mint_data: &[u8], epoch: u64, amount: u64) -> Result<u64, ProgramError> {
+    let mint = StateWithExtensions::<Mint>::unpack(mint_data)?;
+    match mint.get_extension::<TransferFeeConfig>() {
+        Ok(config) => config
+            .calculate_epoch_fee(epoch, amount)
+            .ok_or(ProgramError::ArithmeticOverflow),
+        Err(_) => Ok(0),
+    }
+}
+
+/// Transfer fees withheld in a Token-2022 token account; `0` for accounts
+/// without the extension. An account holding any cannot be closed.
+pub fn withheld_transfer_fees(account_data: &[u8]) -> Result<u64, ProgramError> {
+    let account = StateWithExtensions::<TokenAccount>::unpack(account_data)?;
+    match account.get_extension::<TransferFeeAmount>() {
+        Ok(fees) => Ok(u64::from(fees.withheld_amount)),
+        Err(_) => Ok(0),
+    }
+}
+
+pub fn gateway_stats_pda(gateway: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"stats", gateway.as_ref()], program_id)
+}
+
+pub fn plan_pda(gateway: &Pubkey, plan_id: u64, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[b"plan", gateway.as_ref(), &plan_id.to_le_bytes()],
+        program_id,
+    )
+}
@@ -1,4 +1,10 @@
-use solagate::logic::{apply_consume, ConsumeError, ConsumerRuntimeState, GatewayRules};
+use solagate::{
+    logic::{
+        apply_consume, apply_consume_units, initial_quota, CongestionLoad, ConsumeError,
+        ConsumerRuntimeState, GatewayRules,
+    },
+    pricing::{PricingModel, VolumeDiscountTier, MAX_VOLUME_DISCOUNT_TIERS},
+};
 
 #[test]
 fn consume_updates_counters_and_charges_balance() {
@@ -9,6 +15,7 @@ fn consume_updates_counters_and_charges_balance() {
         period_seconds: 60,
         bucket_capacity: 10,
         refill_per_second: 2,
+        ..GatewayRules::default()
     };
 
     let mut state = ConsumerRuntimeState {
@@ -18,6 +25,7 @@ fn consume_updates_counters_and_charges_balance() {
         quota_period_start_ts: 100,
         total_calls: 0,
         total_spent_lamports: 0,
+        ..ConsumerRuntimeState::default()
     };
 
     let charge = apply_consume(&rules, &mut state, 101, 5_000_000, 1_000_000).expect("consume ok");
@@ -36,6 +44,7 @@ fn consume_fails_when_bucket_empty() {
         period_seconds: 60,
         bucket_capacity: 1,
         refill_per_second: 0,
+        ..GatewayRules::default()
     };
 
     let mut state = ConsumerRuntimeState {
@@ -45,6 +54,7 @@ fn consume_fails_when_bucket_empty() {
         quota_period_start_ts: 100,
         total_calls: 0,
         total_spent_lamports: 0,
+        ..ConsumerRuntimeState::default()
     };
 
     let err = apply_consume(&rules, &mut state, 101, 5_000_000, 1_000_000)
@@ -61,6 +71,7 @@ fn consume_fails_when_balance_below_rent_floor() {
         period_seconds: 60,
         bucket_capacity: 10,
         refill_per_second: 0,
+        ..GatewayRules::default()
     };
 
     let mut state = ConsumerRuntimeState {
@@ -70,6 +81,7 @@ fn consume_fails_when_balance_below_rent_floor() {
         quota_period_start_ts: 100,
         total_calls: 0,
         total_spent_lamports: 0,
+        ..ConsumerRuntimeState::default()
     };
 
     let err = apply_consume(&rules, &mut state, 101, 1_000_100, 1_000_000)
@@ -86,6 +98,7 @@ fn failed_charge_does_not_consume_quota_or_tokens() {
         period_seconds: 60,
         bucket_capacity: 10,
         refill_per_second: 0,
+        ..GatewayRules::default()
     };
 
     let mut state = ConsumerRuntimeState {
@@ -95,6 +108,7 @@ fn failed_charge_does_not_consume_quota_or_tokens() {
         quota_period_start_ts: 100,
         total_calls: 0,
         total_spent_lamports: 0,
+        ..ConsumerRuntimeState::default()
     };
 
     let err =
@@ -104,3 +118,323 @@ fn failed_charge_does_not_consume_quota_or_tokens() {
     assert_eq!(state.quota_remaining, 100);
     assert_eq!(state.total_calls, 0);
 }
+
+#[test]
+fn weighted_consume_burns_units_and_multiplies_price() {
+    let rules = GatewayRules {
+        base_price_lamports: 1_000,
+        max_surge_bps: 0,
+        period_limit: 100,
+        period_seconds: 60,
+        bucket_capacity: 10,
+        refill_per_second: 0,
+        ..GatewayRules::default()
+    };
+
+    let mut state = ConsumerRuntimeState {
+        bucket_tokens: 10,
+        bucket_last_refill_ts: 100,
+        quota_remaining: 100,
+        quota_period_start_ts: 100,
+        total_calls: 0,
+        total_spent_lamports: 0,
+        ..ConsumerRuntimeState::default()
+    };
+
+    let charge = apply_consume_units(&rules, &mut state, 4, 101, 5_000_000, 1_000_000)
+        .expect("weighted consume ok");
+    assert_eq!(charge, 4_000);
+    assert_eq!(state.bucket_tokens, 6);
+    assert_eq!(state.quota_remaining, 96);
+    assert_eq!(state.total_calls, 1);
+    assert_eq!(state.total_spent_lamports, 4_000);
+}
+
+#[test]
+fn weighted_consume_needs_enough_tokens_and_quota_for_all_units() {
+    let rules = GatewayRules {
+        base_price_lamports: 1_000,
+        max_surge_bps: 0,
+        period_limit: 100,
+        period_seconds: 60,
+        bucket_capacity: 10,
+        refill_per_second: 0,
+        ..GatewayRules::default()
+    };
+
+    let mut state = ConsumerRuntimeState {
+        bucket_tokens: 3,
+        bucket_last_refill_ts: 100,
+        quota_remaining: 100,
+        quota_period_start_ts: 100,
+        total_calls: 0,
+        total_spent_lamports: 0,
+        ..ConsumerRuntimeState::default()
+    };
+
+    let err = apply_consume_units(&rules, &mut state, 4, 101, 5_000_000, 1_000_000)
+        .expect_err("should rate limit");
+    assert_eq!(err, ConsumeError::RateLimited);
+    assert_eq!(state.bucket_tokens, 3);
+
+    state.bucket_tokens = 10;
+    state.quota_remaining = 2;
+    let err = apply_consume_units(&rules, &mut state, 4, 101, 5_000_000, 1_000_000)
+        .expect_err("should exceed quota");
+    assert_eq!(err, ConsumeError::QuotaExceeded);
+    assert_eq!(state.bucket_tokens, 10);
+    assert_eq!(state.quota_remaining, 2);
+}
+
+#[test]
+fn weighted_consume_rejects_zero_units_and_overflowing_charge() {
+    let rules = GatewayRules {
+        base_price_lamports: u64::MAX / 2,
+        max_surge_bps: 0,
+        period_limit: 0,
+        period_seconds: 0,
+        bucket_capacity: 0,
+        refill_per_second: 0,
+        ..GatewayRules::default()
+    };
+
+    let mut state = ConsumerRuntimeState {
+        bucket_tokens: 0,
+        bucket_last_refill_ts: 100,
+        quota_remaining: 0,
+        quota_period_start_ts: 100,
+        total_calls: 0,
+        total_spent_lamports: 0,
+        ..ConsumerRuntimeState::default()
+    };
+
+    let err = apply_consume_units(&rules, &mut state, 0, 101, u64::MAX, 0)
+        .expect_err("zero units rejected");
+    assert_eq!(err, ConsumeError::InvalidUnits);
+
+    let err =
+        apply_consume_units(&rules, &mut state, 3, 101, u64::MAX, 0).expect_err("charge overflows");
+    assert_eq!(err, ConsumeError::ChargeOverflow);
+    assert_eq!(state.total_calls, 0);
+}
+
+#[test]
+fn volume_discount_applies_after_surge_from_lifetime_calls() {
+    let mut volume_discounts = [VolumeDiscountTier::default(); MAX_VOLUME_DISCOUNT_TIERS];
+    volume_discounts[0] = VolumeDiscountTier {
+        min_total_calls: 10,
+        discount_bps: 1_000,
+    };
+    volume_discounts[1] = VolumeDiscountTier {
+        min_total_calls: 1_000_000,
+        discount_bps: 5_000,
+    };
+    let rules = GatewayRules {
+        base_price_lamports: 1_000,
+        max_surge_bps: 10_000,
+        period_limit: 2,
+        period_seconds: 60,
+        volume_discounts,
+        ..GatewayRules::default()
+    };
+
+    let mut state = ConsumerRuntimeState {
+        bucket_tokens: 0,
+        bucket_last_refill_ts: 100,
+        quota_remaining: 2,
+        quota_period_start_ts: 100,
+        total_calls: 9,
+        total_spent_lamports: 0,
+        ..ConsumerRuntimeState::default()
+    };
+
+    // Call 10 is priced before the tier is reached: 50% utilization -> 1_500.
+    let charge = apply_consume(&rules, &mut state, 100, u64::MAX, 0).expect("first call");
+    assert_eq!(charge, 1_500);
+
+    // Call 11 sees 10 prior calls: full surge 2_000, then 10% off.
+    let charge = apply_consume(&rules, &mut state, 100, u64::MAX, 0).expect("second call");
+    assert_eq!(charge, 1_800);
+    assert_eq!(state.total_spent_lamports, 3_300);
+}
+
+#[test]
+fn fractional_refill_keeps_remainder_between_calls() {
+    // 0.4 tokens per second.
+    let rules = GatewayRules {
+        bucket_capacity: 5,
+        refill_per_second: 100,
+        refill_milli_tokens: 400,
+        refill_interval_seconds: 1,
+        ..GatewayRules::default()
+    };
+
+    let mut state = ConsumerRuntimeState {
+        bucket_tokens: 1,
+        bucket_last_refill_ts: 100,
+        ..ConsumerRuntimeState::default()
+    };
+
+    apply_consume(&rules, &mut state, 100, u64::MAX, 0).expect("banked token");
+    let err = apply_consume(&rules, &mut state, 102, u64::MAX, 0).expect_err("0.8 tokens");
+    assert_eq!(err, ConsumeError::RateLimited);
+    assert_eq!(state.bucket_tokens, 0);
+
+    apply_consume(&rules, &mut state, 103, u64::MAX, 0).expect("1.2 tokens");
+    assert_eq!(state.bucket_tokens, 0);
+    assert_eq!(state.bucket_milli_remainder, 200);
+}
+
+#[test]
+fn congestion_load_replaces_own_quota_utilization() {
+    let quiet = GatewayRules {
+        base_price_lamports: 1_000,
+        max_surge_bps: 10_000,
+        period_limit: 100,
+        period_seconds: 60,
+        ..GatewayRules::default()
+    };
+    let busy = GatewayRules {
+        congestion: Some(CongestionLoad {
+            recent_calls: 750,
+            target_calls: 1_000,
+        }),
+        ..quiet
+    };
+    let state = ConsumerRuntimeState {
+        quota_remaining: 100,
+        quota_period_start_ts: 100,
+        ..ConsumerRuntimeState::default()
+    };
+
+    // A fresh consumer pays 1% surge on its own quota, but 75% when the gateway is busy.
+    let charge = apply_consume(&quiet, &mut state.clone(), 100, u64::MAX, 0).expect("quiet");
+    assert_eq!(charge, 1_010);
+    let charge = apply_consume(&busy, &mut state.clone(), 100, u64::MAX, 0).expect("busy");
+    assert_eq!(charge, 1_750);
+
+    // Load above the target is priced as full utilization.
+    let overloaded = GatewayRules {
+        congestion: Some(CongestionLoad {
+            recent_calls: 5_000,
+            target_calls: 1_000,
+        }),
+        ..quiet
+    };
+    let charge = apply_consume(&overloaded, &mut state.clone(), 100, u64::MAX, 0).expect("over");
+    assert_eq!(charge, 2_000);
+}
+
+#[test]
+fn free_allowance_is_used_before_charging() {
+    let rules = GatewayRules {
+        base_price_lamports: 1_000,
+        period_limit: 100,
+        period_seconds: 60,
+        bucket_capacity: 3,
+        free_calls_per_period: 2,
+        trial_calls: 1,
+        pricing: PricingModel::Flat,
+        ..GatewayRules::default()
+    };
+    let mut state = ConsumerRuntimeState {
+        bucket_tokens: 3,
+        bucket_last_refill_ts: 100,
+        quota_remaining: 100,
+        quota_period_start_ts: 100,
+        free_period_start_ts: 100,
+        ..ConsumerRuntimeState::default()
+    };
+
+    // Two period units, then the trial unit, then one charged unit.
+    let charge = apply_consume_units(&rules, &mut state, 2, 100, 0, 0).expect("free");
+    assert_eq!(charge, 0);
+    let charge = apply_consume_units(&rules, &mut state, 1, 100, 0, 0).expect("trial");
+    assert_eq!(charge, 0);
+    assert_eq!(state.trial_calls_used, 1);
+    assert_eq!(state.total_calls, 2);
+
+    // Free calls still spend rate-limit tokens.
+    let err = apply_consume_units(&rules, &mut state, 1, 100, u64::MAX, 0).expect_err("limited");
+    assert_eq!(err, ConsumeError::RateLimited);
+
+    // A new period restores the period units but not the trial.
+    let mut next = ConsumerRuntimeState {
+        bucket_tokens: 3,
+        ..state
+    };
+    let charge = apply_consume_units(&rules, &mut next, 3, 160, u64::MAX, 0).expect("next period");
+    assert_eq!(charge, 1_000);
+    assert_eq!(next.free_calls_used, 2);
+    assert_eq!(next.free_period_start_ts, 160);
+    assert_eq!(next.total_spent_lamports, 1_000);
+}
+
+#[test]
+fn period_limit_change_mid_period_applies_to_stored_counters() {
+    let rules = GatewayRules {
+        base_price_lamports: 1_000,
+        period_limit: 100,
+        period_seconds: 3_600,
+        ..GatewayRules::default()
+    };
+    let mut state = ConsumerRuntimeState {
+        quota_remaining: initial_quota(rules.period_limit),
+        quota_period_limit: rules.period_limit,
+        ..ConsumerRuntimeState::default()
+    };
+    for now_ts in 0..10 {
+        apply_consume(&rules, &mut state, now_ts, u64::MAX, 0).expect("within limit");
+    }
+    assert_eq!(state.quota_remaining, 90);
+
+    // Lowered below the 10 units already used: blocked until the period rolls over.
+    let lowered = GatewayRules {
+        period_limit: 5,
+        ..rules
+    };
+    let err = apply_consume(&lowered, &mut state, 10, u64::MAX, 0).expect_err("already used");
+    assert_eq!(err, ConsumeError::QuotaExceeded);
+
+    // Raised again: the units used under the old limit still count.
+    let raised = GatewayRules {
+        period_limit: 12,
+        ..rules
+    };
+    apply_consume(&raised, &mut state, 11, u64::MAX, 0).expect("within raised limit");
+    assert_eq!(state.quota_remaining, 1);
+    assert_eq!(state.quota_period_limit, 12);
+
+    apply_consume(&lowered, &mut state, 3_600, u64::MAX, 0).expect("next period");
+    assert_eq!(state.quota_remaining, 4);
+
+    // First-release consumers did not record their limit; what is left is capped.
+    let mut state = ConsumerRuntimeState {
+        quota_remaining: 90,
+        ..ConsumerRuntimeState::default()
+    };
+    apply_consume(&lowered, &mut state, 10, u64::MAX, 0).expect("within lowered limit");
+    assert_eq!(state.quota_remaining, 4);
+
+    // Enabling a lifetime limit on a consumer registered without one.
+    let unlimited = GatewayRules {
+        period_limit: 0,
+        period_seconds: 0,
+        ..rules
+    };
+    let mut state = ConsumerRuntimeState {
+        quota_remaining: initial_quota(unlimited.period_limit),
+        ..ConsumerRuntimeState::default()
+    };
+    apply_consume(&unlimited, &mut state, 10, u64::MAX, 0).expect("no limit");
+
+    let lifetime = GatewayRules {
+        period_limit: 3,
+        ..unlimited
+    };
+    for now_ts in 11..14 {
+        apply_consume(&lifetime, &mut state, now_ts, u64::MAX, 0).expect("seeded quota");
+    }
+    let err = apply_consume(&lifetime, &mut state, 14, u64::MAX, 0).expect_err("exhausted");
+    assert_eq!(err, ConsumeError::QuotaExceeded);
+}
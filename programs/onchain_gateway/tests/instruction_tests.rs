@@ -1,5 +1,10 @@
 use solagate::{
-    instruction::GatewayInstruction, state::consumer_pda, state::gateway_pda, ID,
+    instruction::{BatchConsumeEntry, BatchConsumeOutcome, GatewayInstruction, MAX_BATCH_ENTRIES},
+    state::{
+        consumer_pda, consumer_vault_pda, gateway_pda, gateway_stats_pda, plan_pda,
+        route_policy_pda, BackendSigner, ConsumerAccount, ConsumerStatus,
+    },
+    ID,
 };
 use solana_sdk::pubkey::Pubkey;
 
@@ -16,11 +21,120 @@ fn instruction_roundtrip_works() {
     assert_eq!(decoded, ix);
 }
 
+#[test]
+fn update_gateway_config_roundtrip_keeps_unset_fields() {
+    let ix = GatewayInstruction::UpdateGatewayConfig {
+        base_price_lamports: Some(2_500),
+        max_surge_bps: None,
+        period_limit: Some(500),
+        period_seconds: None,
+        bucket_capacity: None,
+        refill_per_second: Some(3),
+    };
+
+    let encoded = ix.pack().expect("serialize");
+    let decoded = GatewayInstruction::unpack(&encoded).expect("deserialize");
+
+    assert_eq!(decoded, ix);
+}
+
+#[test]
+fn admin_transfer_instructions_roundtrip() {
+    let propose = GatewayInstruction::ProposeAdmin {
+        new_admin: Pubkey::new_unique(),
+    };
+    let encoded = propose.pack().expect("serialize");
+    assert_eq!(
+        GatewayInstruction::unpack(&encoded).expect("deserialize"),
+        propose
+    );
+
+    let accept = GatewayInstruction::AcceptAdmin;
+    let encoded = accept.pack().expect("serialize");
+    assert_eq!(
+        GatewayInstruction::unpack(&encoded).expect("deserialize"),
+        accept
+    );
+}
+
+#[test]
+fn backend_signer_expiry_is_exclusive() {
+    let signer = BackendSigner {
+        signer: Pubkey::new_unique(),
+        expires_at_ts: 1_000,
+    };
+    assert!(signer.is_active(999));
+    assert!(!signer.is_active(1_000));
+
+    let no_expiry = BackendSigner {
+        expires_at_ts: 0,
+        ..signer
+    };
+    assert!(no_expiry.is_active(i64::MAX));
+    assert!(!BackendSigner::default().is_active(0));
+}
+
+#[test]
+fn rotated_api_key_keeps_previous_hash_until_grace_deadline() {
+    let consumer = ConsumerAccount {
+        is_initialized: true,
+        api_key_hash: [2u8; 32],
+        previous_api_key_hash: [1u8; 32],
+        previous_key_expires_ts: 500,
+        ..ConsumerAccount::default()
+    };
+
+    assert!(consumer.accepts_api_key(&[2u8; 32], 10_000));
+    assert!(consumer.accepts_api_key(&[1u8; 32], 499));
+    assert!(!consumer.accepts_api_key(&[1u8; 32], 500));
+    assert!(!consumer.accepts_api_key(&[3u8; 32], 0));
+}
+
+#[test]
+fn consumer_status_defaults_to_active_and_encodes_as_one_byte() {
+    assert_eq!(ConsumerStatus::default(), ConsumerStatus::Active);
+    assert_eq!(
+        borsh::to_vec(&ConsumerStatus::Revoked).expect("serialize"),
+        vec![2]
+    );
+}
+
+#[test]
+fn max_batch_outcomes_fit_in_return_data() {
+    let outcomes = vec![
+        BatchConsumeOutcome {
+            charged_lamports: u64::MAX,
+            error_code: u64::MAX,
+            retry_after_ms: u64::MAX,
+        };
+        MAX_BATCH_ENTRIES
+    ];
+    let encoded = borsh::to_vec(&outcomes).expect("serialize");
+    assert!(encoded.len() <= solana_sdk::program::MAX_RETURN_DATA);
+
+    let ix = GatewayInstruction::ConsumeBatch {
+        entries: vec![
+            BatchConsumeEntry {
+                api_key_id: 1,
+                presented_api_key_hash: [9u8; 32],
+                units: 2,
+                route_id: Some(4),
+            };
+            3
+        ],
+    };
+    let encoded = ix.pack().expect("serialize");
+    assert_eq!(
+        GatewayInstruction::unpack(&encoded).expect("deserialize"),
+        ix
+    );
+}
+
 #[test]
 fn pda_derivation_is_deterministic() {
-    let admin = Pubkey::new_unique();
+    let creator = Pubkey::new_unique();
     let owner = Pubkey::new_unique();
-    let (gateway, _) = gateway_pda(&admin, &ID);
+    let (gateway, _) = gateway_pda(&creator, &ID);
 
     let (consumer_a, _) = consumer_pda(&gateway, &owner, 11, &ID);
     let (consumer_b, _) = consumer_pda(&gateway, &owner, 11, &ID);
@@ -28,4 +142,32 @@ fn pda_derivation_is_deterministic() {
 
     assert_eq!(consumer_a, consumer_b);
     assert_ne!(consumer_a, consumer_c);
+
+    let (route_a, _) = route_policy_pda(&gateway, 1, &ID);
+    let (route_b, _) = route_policy_pda(&gateway, 2, &ID);
+    assert_ne!(route_a, route_b);
+    assert_eq!(route_a, route_policy_pda(&gateway, 1, &ID).0);
+
+    let (plan, _) = plan_pda(&gateway, 1, &ID);
+    assert_ne!(plan, route_a);
+    assert_eq!(plan, plan_pda(&gateway, 1, &ID).0);
+
+    let (stats, _) = gateway_stats_pda(&gateway, &ID);
+    assert_ne!(stats, gateway);
+    assert_eq!(stats, gateway_stats_pda(&gateway, &ID).0);
+
+    let (vault, _) = consumer_vault_pda(&consumer_a, &ID);
+    assert_ne!(vault, consumer_vault_pda(&consumer_c, &ID).0);
+    assert_eq!(vault, consumer_vault_pda(&consumer_a, &ID).0);
+}
+
+#[test]
+fn change_plan_roundtrip_distinguishes_gateway_rules() {
+    for plan_id in [Some(2), None] {
+        let ix = GatewayInstruction::ChangePlan { plan_id };
+        let decoded =
+            GatewayInstruction::unpack(&ix.pack().expect("serialize")).expect("deserialize");
+        assert_eq!(decoded, ix);
+    }
+    assert!(!ConsumerAccount::default().has_plan());
 }
@@ -0,0 +1,273 @@
+use solagate::{
+    layout::{stored_layout, upgrade_layout, StoredLayout, VersionedLayout},
+    state::{
+        mint_transfer_fee, BackendSigner, ConsumerAccount, ConsumerStatus, GatewayConfig,
+        GatewayStats, Plan, RoutePolicy,
+    },
+};
+use solana_sdk::{program_pack::Pack, pubkey::Pubkey};
+use spl_token_2022::{
+    extension::{
+        transfer_fee::{TransferFee, TransferFeeConfig},
+        BaseStateWithExtensionsMut, ExtensionType, StateWithExtensionsMut,
+    },
+    state::Mint,
+};
+
+fn sample_gateway() -> GatewayConfig {
+    GatewayConfig {
+        discriminator: GatewayConfig::DISCRIMINATOR,
+        version: GatewayConfig::VERSION,
+        is_initialized: true,
+        admin: Pubkey::new_unique(),
+        creator: Pubkey::new_unique(),
+        treasury: Pubkey::new_unique(),
+        base_price_lamports: 10_000,
+        max_surge_bps: 5_000,
+        period_limit: 1_000,
+        period_seconds: 60,
+        bucket_capacity: 20,
+        refill_per_second: 5,
+        consumer_count: 3,
+        bump: 254,
+        ..GatewayConfig::default()
+    }
+}
+
+fn sample_consumer() -> ConsumerAccount {
+    ConsumerAccount {
+        discriminator: ConsumerAccount::DISCRIMINATOR,
+        version: ConsumerAccount::VERSION,
+        is_initialized: true,
+        gateway: Pubkey::new_unique(),
+        owner: Pubkey::new_unique(),
+        api_key_id: 9,
+        api_key_hash: [4u8; 32],
+        bucket_tokens: 7,
+        quota_remaining: 900,
+        total_calls: 100,
+        total_spent_lamports: 1_000_000,
+        bump: 253,
+        ..ConsumerAccount::default()
+    }
+}
+
+#[test]
+fn serialized_sizes_match_declared_lengths() {
+    assert_eq!(
+        borsh::to_vec(&sample_gateway()).expect("serialize").len(),
+        GatewayConfig::LEN
+    );
+    assert_eq!(
+        borsh::to_vec(&sample_consumer()).expect("serialize").len(),
+        ConsumerAccount::LEN
+    );
+    assert_eq!(
+        borsh::to_vec(&RoutePolicy::default())
+            .expect("serialize")
+            .len(),
+        RoutePolicy::LEN
+    );
+    assert_eq!(
+        borsh::to_vec(&Plan::default()).expect("serialize").len(),
+        Plan::LEN
+    );
+    assert_eq!(
+        borsh::to_vec(&GatewayStats::default())
+            .expect("serialize")
+            .len(),
+        GatewayStats::LEN
+    );
+    assert_ne!(GatewayConfig::BASELINE_LEN, ConsumerAccount::BASELINE_LEN);
+}
+
+#[test]
+fn baseline_gateway_maps_fields_into_current_layout() {
+    let admin = Pubkey::new_unique();
+    let treasury = Pubkey::new_unique();
+    let backend_signer = Pubkey::new_unique();
+    // Field by field, as the first release wrote it.
+    let mut baseline = vec![1u8];
+    baseline.extend_from_slice(admin.as_ref());
+    baseline.extend_from_slice(treasury.as_ref());
+    baseline.extend_from_slice(backend_signer.as_ref());
+    baseline.extend_from_slice(&10_000u64.to_le_bytes());
+    baseline.extend_from_slice(&5_000u16.to_le_bytes());
+    baseline.extend_from_slice(&1_000u64.to_le_bytes());
+    baseline.extend_from_slice(&60i64.to_le_bytes());
+    baseline.extend_from_slice(&20u64.to_le_bytes());
+    baseline.extend_from_slice(&5u64.to_le_bytes());
+    baseline.push(254);
+    assert_eq!(baseline.len(), GatewayConfig::BASELINE_LEN);
+
+    assert_eq!(
+        stored_layout::<GatewayConfig>(&baseline),
+        StoredLayout::Outdated
+    );
+    assert_eq!(upgrade_layout::<ConsumerAccount>(&baseline), None);
+    let upgraded = upgrade_layout::<GatewayConfig>(&baseline).expect("upgrade");
+    assert_eq!(upgraded.len(), GatewayConfig::LEN);
+
+    let decoded = borsh::from_slice::<GatewayConfig>(&upgraded).expect("decode");
+    assert_eq!(decoded.version, GatewayConfig::VERSION);
+    assert_eq!(decoded.admin, admin);
+    assert_eq!(decoded.creator, admin);
+    assert!(!decoded.has_pending_admin());
+    assert_eq!(decoded.treasury, treasury);
+    assert_eq!(
+        decoded.backend_signers[0],
+        BackendSigner {
+            signer: backend_signer,
+            expires_at_ts: 0,
+        }
+    );
+    assert!(decoded.backend_signers[1..]
+        .iter()
+        .all(|entry| *entry == BackendSigner::default()));
+    assert!(!decoded.paused);
+    assert_eq!(decoded.consumer_count, 0);
+    assert_eq!(decoded.base_price_lamports, 10_000);
+    assert_eq!(decoded.max_surge_bps, 5_000);
+    assert_eq!(decoded.period_limit, 1_000);
+    assert_eq!(decoded.period_seconds, 60);
+    assert_eq!(decoded.bucket_capacity, 20);
+    assert_eq!(decoded.refill_per_second, 5);
+    assert_eq!(decoded.bump, 254);
+}
+
+#[test]
+fn baseline_consumer_maps_fields_into_current_layout() {
+    let gateway = Pubkey::new_unique();
+    let owner = Pubkey::new_unique();
+    let mut baseline = vec![1u8];
+    baseline.extend_from_slice(gateway.as_ref());
+    baseline.extend_from_slice(owner.as_ref());
+    baseline.extend_from_slice(&9u64.to_le_bytes());
+    baseline.extend_from_slice(&[4u8; 32]);
+    for value in [7u64, 100, 900, 50, 100, 1_000_000] {
+        baseline.extend_from_slice(&value.to_le_bytes());
+    }
+    baseline.push(253);
+    assert_eq!(baseline.len(), ConsumerAccount::BASELINE_LEN);
+
+    assert_eq!(upgrade_layout::<GatewayConfig>(&baseline), None);
+    let upgraded = upgrade_layout::<ConsumerAccount>(&baseline).expect("upgrade");
+    let decoded = borsh::from_slice::<ConsumerAccount>(&upgraded).expect("decode");
+    assert_eq!(
+        decoded,
+        ConsumerAccount {
+            gateway,
+            owner,
+            bucket_last_refill_ts: 100,
+            quota_period_start_ts: 50,
+            ..sample_consumer()
+        }
+    );
+    assert_eq!(decoded.status, ConsumerStatus::Active);
+    assert_eq!(decoded.previous_key_expires_ts, 0);
+}
+
+#[test]
+fn older_plans_upgrade_to_whole_token_refill_without_free_tier() {
+    let plan = Plan {
+        discriminator: Plan::DISCRIMINATOR,
+        version: Plan::VERSION,
+        is_initialized: true,
+        plan_id: 3,
+        bucket_capacity: 50,
+        refill_per_second: 5,
+        ..Plan::default()
+    };
+    let current = borsh::to_vec(&plan).expect("serialize");
+
+    for (version, len) in [(1, Plan::V1_LEN), (2, Plan::V2_LEN)] {
+        let mut old = current[..len].to_vec();
+        old[8] = version;
+
+        assert_eq!(upgrade_layout::<Plan>(&old).as_ref(), Some(&current));
+    }
+}
+
+#[test]
+fn v1_stats_upgrade_without_congestion_pricing() {
+    let stats = GatewayStats {
+        discriminator: GatewayStats::DISCRIMINATOR,
+        version: GatewayStats::VERSION,
+        is_initialized: true,
+        period_limit: 1_000,
+        bucket_capacity: 50,
+        ..GatewayStats::default()
+    };
+    let current = borsh::to_vec(&stats).expect("serialize");
+    let mut v1 = current[..GatewayStats::V1_LEN].to_vec();
+    v1[8] = 1;
+
+    let upgraded = upgrade_layout::<GatewayStats>(&v1).expect("upgrade");
+    assert_eq!(upgraded, current);
+    let decoded = borsh::from_slice::<GatewayStats>(&upgraded).expect("decode");
+    assert!(!decoded.has_congestion_pricing());
+}
+
+#[test]
+fn current_and_foreign_layouts_are_not_upgraded() {
+    let gateway = borsh::to_vec(&sample_gateway()).expect("serialize");
+    assert_eq!(
+        stored_layout::<GatewayConfig>(&gateway),
+        StoredLayout::Current
+    );
+    assert_eq!(upgrade_layout::<GatewayConfig>(&gateway), None);
+    assert_eq!(
+        stored_layout::<ConsumerAccount>(&gateway),
+        StoredLayout::Unrecognized
+    );
+
+    let zeroed = vec![0u8; GatewayConfig::BASELINE_LEN];
+    assert_eq!(
+        stored_layout::<GatewayConfig>(&zeroed),
+        StoredLayout::Unrecognized
+    );
+
+    // Only the baseline layout was ever deployed before the current one.
+    let mut older = gateway[..GatewayConfig::LEN - 16].to_vec();
+    older[8] = GatewayConfig::VERSION - 1;
+    assert_eq!(
+        stored_layout::<GatewayConfig>(&older),
+        StoredLayout::Unrecognized
+    );
+}
+
+#[test]
+fn transfer_fee_mint_withholds_part_of_each_transfer() {
+    let base = Mint {
+        decimals: 6,
+        is_initialized: true,
+        ..Mint::default()
+    };
+    let mut plain = vec![0u8; Mint::LEN];
+    Mint::pack(base, &mut plain).expect("pack mint");
+    assert_eq!(mint_transfer_fee(&plain, 0, 1_000_000).expect("fee"), 0);
+
+    let len = ExtensionType::try_calculate_account_len::<Mint>(&[ExtensionType::TransferFeeConfig])
+        .expect("mint len");
+    let mut data = vec![0u8; len];
+    let mut mint =
+        StateWithExtensionsMut::<Mint>::unpack_uninitialized(&mut data).expect("uninitialized");
+    let fee = TransferFee {
+        epoch: 0.into(),
+        maximum_fee: 5_000.into(),
+        transfer_fee_basis_points: 100.into(),
+    };
+    let config = mint
+        .init_extension::<TransferFeeConfig>(true)
+        .expect("extension");
+    config.older_transfer_fee = fee;
+    config.newer_transfer_fee = fee;
+    mint.base = base;
+    mint.pack_base();
+    mint.init_account_type().expect("account type");
+
+    // 1% rounded up, capped at the maximum fee.
+    assert_eq!(mint_transfer_fee(&data, 3, 10_001).expect("fee"), 101);
+    assert_eq!(mint_transfer_fee(&data, 3, 1_000_000).expect("fee"), 5_000);
+    assert_eq!(mint_transfer_fee(&data, 3, 0).expect("fee"), 0);
+}
@@ -1,6 +1,14 @@
-use solagate::logic::{
-    can_charge, dynamic_price_lamports, enforce_quota_window, refill_bucket, BucketState,
-    QuotaState,
+use solagate::{
+    logic::{
+        advance_sliding_window, apply_aggregate_limits, apply_consume_units, apply_overrides,
+        can_charge, dynamic_price_lamports, enforce_quota_window, gcra_admit, gcra_retry_after_ms,
+        rebase_aggregate_usage, rebase_runtime_state, record_congestion, refill_bucket,
+        refill_milli_bucket, sliding_window_estimate, sliding_window_upper_bound, split_charge,
+        AggregateLimits, AggregateUsage, BucketState, ConsumeError, ConsumerRuntimeState,
+        GatewayRules, GcraParams, MilliBucketState, QuotaState, RateLimiter, SlidingWindowState,
+    },
+    pricing::PricingModel,
+    state::{ConsumerOverrides, QuotaMode},
 };
 
 #[test]
@@ -45,3 +53,453 @@ fn charge_checks_post_rent_balance() {
     assert!(can_charge(2_000_000, 1_000_000, 500_000));
     assert!(!can_charge(1_400_000, 1_000_000, 500_000));
 }
+
+#[test]
+fn withdraw_can_drain_down_to_rent_floor_only() {
+    assert!(can_charge(1_500_000, 1_000_000, 500_000));
+    assert!(!can_charge(1_500_000, 1_000_000, 500_001));
+    assert!(!can_charge(u64::MAX, 1, u64::MAX));
+}
+
+#[test]
+fn aggregate_limits_are_shared_and_all_or_nothing() {
+    let limits = AggregateLimits {
+        period_limit: 5,
+        period_seconds: 60,
+        bucket_capacity: 3,
+        refill_per_second: 1,
+    };
+    let mut usage = AggregateUsage {
+        bucket_tokens: 3,
+        bucket_last_refill_ts: 100,
+        quota_remaining: 5,
+        quota_period_start_ts: 100,
+    };
+
+    apply_aggregate_limits(&limits, &mut usage, 3, 100).expect("first caller");
+    assert_eq!(usage.bucket_tokens, 0);
+    assert_eq!(usage.quota_remaining, 2);
+
+    let err = apply_aggregate_limits(&limits, &mut usage, 1, 100).expect_err("bucket drained");
+    assert_eq!(err, ConsumeError::RateLimited);
+
+    let err = apply_aggregate_limits(&limits, &mut usage, 3, 103).expect_err("quota short");
+    assert_eq!(err, ConsumeError::QuotaExceeded);
+    assert_eq!(usage.bucket_tokens, 0);
+    assert_eq!(usage.bucket_last_refill_ts, 100);
+
+    apply_aggregate_limits(&limits, &mut usage, 2, 103).expect("refilled");
+    assert_eq!(usage.bucket_tokens, 1);
+    assert_eq!(usage.quota_remaining, 0);
+}
+
+#[test]
+fn aggregate_limit_change_keeps_usage_of_current_period() {
+    let limits = AggregateLimits {
+        period_limit: 100,
+        period_seconds: 3_600,
+        bucket_capacity: 20,
+        refill_per_second: 1,
+    };
+    let mut usage = AggregateUsage {
+        bucket_tokens: 15,
+        bucket_last_refill_ts: 100,
+        quota_remaining: 30,
+        quota_period_start_ts: 0,
+    };
+
+    // A capacity cut below what was already used blocks the rest of the period.
+    let cut = AggregateLimits {
+        period_limit: 50,
+        bucket_capacity: 10,
+        ..limits
+    };
+    rebase_aggregate_usage(&limits, &cut, &mut usage, 200);
+    assert_eq!(usage.bucket_tokens, 10);
+    assert_eq!(usage.quota_remaining, 0);
+    assert_eq!(usage.quota_period_start_ts, 0);
+    let err = apply_aggregate_limits(&cut, &mut usage, 1, 200).expect_err("already used");
+    assert_eq!(err, ConsumeError::QuotaExceeded);
+
+    // Limits enabled on counters created without them start full.
+    let disabled = AggregateLimits {
+        period_limit: 0,
+        period_seconds: 0,
+        bucket_capacity: 0,
+        refill_per_second: 0,
+    };
+    let mut usage = AggregateUsage {
+        bucket_tokens: 0,
+        bucket_last_refill_ts: 0,
+        quota_remaining: 0,
+        quota_period_start_ts: 0,
+    };
+    rebase_aggregate_usage(&disabled, &cut, &mut usage, 500);
+    assert_eq!(usage.bucket_tokens, 10);
+    assert_eq!(usage.bucket_last_refill_ts, 500);
+    assert_eq!(usage.quota_remaining, 50);
+    assert_eq!(usage.quota_period_start_ts, 500);
+    apply_aggregate_limits(&cut, &mut usage, 1, 500).expect("fresh quota");
+}
+
+#[test]
+fn plan_change_keeps_usage_of_current_period() {
+    let free = GatewayRules {
+        base_price_lamports: 1_000,
+        max_surge_bps: 0,
+        period_limit: 100,
+        period_seconds: 3_600,
+        bucket_capacity: 10,
+        refill_per_second: 1,
+        ..GatewayRules::default()
+    };
+    let pro = GatewayRules {
+        period_limit: 1_000,
+        bucket_capacity: 50,
+        ..free
+    };
+    let mut state = ConsumerRuntimeState {
+        bucket_tokens: 4,
+        bucket_last_refill_ts: 100,
+        quota_remaining: 30,
+        quota_period_start_ts: 0,
+        total_calls: 70,
+        total_spent_lamports: 70_000,
+        ..ConsumerRuntimeState::default()
+    };
+
+    rebase_runtime_state(&free, &pro, &mut state, 200);
+    assert_eq!(state.quota_remaining, 930);
+    assert_eq!(state.bucket_tokens, 4);
+    assert_eq!(state.quota_period_start_ts, 0);
+
+    rebase_runtime_state(&pro, &free, &mut state, 200);
+    assert_eq!(state.quota_remaining, 30);
+
+    let unlimited = GatewayRules {
+        period_limit: 0,
+        bucket_capacity: 0,
+        ..free
+    };
+    rebase_runtime_state(&free, &unlimited, &mut state, 400);
+    rebase_runtime_state(&unlimited, &free, &mut state, 500);
+    assert_eq!(state.quota_remaining, 100);
+    assert_eq!(state.quota_period_start_ts, 500);
+    assert_eq!(state.bucket_tokens, 10);
+    assert_eq!(state.bucket_last_refill_ts, 500);
+}
+
+#[test]
+fn overrides_replace_only_set_fields() {
+    let rules = GatewayRules {
+        base_price_lamports: 10_000,
+        max_surge_bps: 5_000,
+        period_limit: 1_000,
+        period_seconds: 60,
+        bucket_capacity: 20,
+        refill_per_second: 5,
+        ..GatewayRules::default()
+    };
+
+    assert_eq!(
+        apply_overrides(&rules, &ConsumerOverrides::default()),
+        rules
+    );
+
+    let merged = apply_overrides(
+        &rules,
+        &ConsumerOverrides {
+            discount_bps: 2_500,
+            bucket_capacity: 200,
+            ..ConsumerOverrides::default()
+        },
+    );
+    assert_eq!(merged.base_price_lamports, 7_500);
+    assert_eq!(merged.bucket_capacity, 200);
+    assert_eq!(merged.refill_per_second, 5);
+    assert_eq!(merged.period_limit, 1_000);
+    assert_eq!(merged.max_surge_bps, 5_000);
+
+    let free = apply_overrides(
+        &rules,
+        &ConsumerOverrides {
+            discount_bps: 10_000,
+            refill_per_second: 50,
+            period_limit: 1,
+            ..ConsumerOverrides::default()
+        },
+    );
+    assert_eq!(free.base_price_lamports, 0);
+    assert_eq!(free.refill_per_second, 50);
+    assert_eq!(free.period_limit, 1);
+}
+
+#[test]
+fn milli_bucket_refills_whole_intervals_only() {
+    // One token every 10 seconds.
+    let mut bucket = MilliBucketState {
+        capacity: 2,
+        milli_tokens: 0,
+        refill_milli_tokens: 1_000,
+        refill_interval_seconds: 10,
+        last_refill_ts: 100,
+    };
+
+    refill_milli_bucket(&mut bucket, 109);
+    assert_eq!(bucket.milli_tokens, 0);
+    assert_eq!(bucket.last_refill_ts, 100);
+
+    refill_milli_bucket(&mut bucket, 115);
+    assert_eq!(bucket.milli_tokens, 1_000);
+    assert_eq!(bucket.last_refill_ts, 110);
+
+    refill_milli_bucket(&mut bucket, 200);
+    assert_eq!(bucket.milli_tokens, 2_000);
+    assert_eq!(bucket.last_refill_ts, 200);
+
+    // 5 per 100 ms averages to 50 tokens per second.
+    let mut fast = MilliBucketState {
+        capacity: 100,
+        milli_tokens: 0,
+        refill_milli_tokens: 50_000,
+        refill_interval_seconds: 1,
+        last_refill_ts: 0,
+    };
+    refill_milli_bucket(&mut fast, 1);
+    assert_eq!(fast.milli_tokens, 50_000);
+}
+
+/// xorshift64, so the property tests stay reproducible without extra dependencies.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+}
+
+fn sliding_rules(period_limit: u64, period_seconds: i64) -> GatewayRules {
+    GatewayRules {
+        base_price_lamports: 1,
+        period_limit,
+        period_seconds,
+        bucket_capacity: u64::MAX / 2,
+        refill_per_second: u64::MAX / 4,
+        pricing: PricingModel::Flat,
+        quota_mode: QuotaMode::SlidingWindow,
+        ..GatewayRules::default()
+    }
+}
+
+fn run_random_traffic(seed: u64, period_limit: u64, period_seconds: i64) -> Vec<u64> {
+    let rules = sliding_rules(period_limit, period_seconds);
+    let mut rng = Rng(seed);
+    let mut state = ConsumerRuntimeState {
+        bucket_tokens: u64::MAX / 2,
+        quota_remaining: period_limit,
+        ..ConsumerRuntimeState::default()
+    };
+
+    let mut admitted_per_second = vec![0u64; (period_seconds * 12) as usize];
+    for (second, admitted) in admitted_per_second.iter_mut().enumerate() {
+        // Bursty traffic: most seconds are quiet, some try far more than the limit.
+        let attempts = match rng.below(4) {
+            0 => rng.below(period_limit * 2),
+            _ => rng.below(3),
+        };
+        for _ in 0..attempts {
+            let units = 1 + rng.below(3);
+            match apply_consume_units(&rules, &mut state, units, second as i64, u64::MAX, 0) {
+                Ok(_) => *admitted += units,
+                Err(err) => assert_eq!(err, ConsumeError::QuotaExceeded),
+            }
+        }
+    }
+    admitted_per_second
+}
+
+#[test]
+fn sliding_window_never_exceeds_limit_in_any_window() {
+    let (period_limit, period_seconds) = (20u64, 10i64);
+    let period = period_seconds as usize;
+
+    for seed in 1..=200u64 {
+        let admitted = run_random_traffic(seed * 0x9E37_79B9, period_limit, period_seconds);
+
+        for end in 0..admitted.len() {
+            let start = (end + 1).saturating_sub(period);
+            let trailing: u64 = admitted[start..=end].iter().sum();
+            assert!(trailing <= period_limit, "seed {seed} window ending {end}");
+        }
+    }
+}
+
+#[test]
+fn sliding_window_blocks_burst_across_boundary() {
+    let (period_limit, period_seconds) = (10u64, 60i64);
+
+    let mut fixed_rules = sliding_rules(period_limit, period_seconds);
+    fixed_rules.quota_mode = QuotaMode::FixedWindow;
+    let sliding_rules = sliding_rules(period_limit, period_seconds);
+
+    for (rules, expected) in [
+        (fixed_rules, 2 * period_limit),
+        (sliding_rules, period_limit),
+    ] {
+        let mut state = ConsumerRuntimeState {
+            bucket_tokens: u64::MAX / 2,
+            quota_remaining: period_limit,
+            ..ConsumerRuntimeState::default()
+        };
+
+        let admitted = [59, 60]
+            .into_iter()
+            .flat_map(|now| std::iter::repeat_n(now, period_limit as usize))
+            .filter(|now| apply_consume_units(&rules, &mut state, 1, *now, u64::MAX, 0).is_ok())
+            .count() as u64;
+        assert_eq!(admitted, expected, "{:?}", rules.quota_mode);
+    }
+}
+
+#[test]
+fn sliding_window_weights_previous_window_by_overlap() {
+    let mut window = SlidingWindowState {
+        period_seconds: 100,
+        window_start_ts: 0,
+        previous_used: 0,
+        current_used: 80,
+    };
+
+    advance_sliding_window(&mut window, 125);
+    assert_eq!(window.window_start_ts, 100);
+    assert_eq!(window.previous_used, 80);
+    assert_eq!(window.current_used, 0);
+    assert_eq!(sliding_window_estimate(&window, 125), 60);
+    assert_eq!(sliding_window_upper_bound(&window), 80);
+
+    advance_sliding_window(&mut window, 350);
+    assert_eq!(window.window_start_ts, 300);
+    assert_eq!(window.previous_used, 0);
+}
+
+#[test]
+fn gcra_allows_burst_then_spaces_calls() {
+    let params = GcraParams {
+        emission_interval_ms: 250,
+        burst_tolerance_ms: 500,
+    };
+
+    // Tolerance of two intervals admits a burst of three calls.
+    let mut tat = 0;
+    for _ in 0..3 {
+        tat = gcra_admit(&params, tat, 1, 10_000).expect("within burst");
+    }
+    assert_eq!(tat, 10_750);
+    assert_eq!(gcra_admit(&params, tat, 1, 10_000), Err(250));
+
+    // Waiting exactly the reported time admits the next call.
+    assert_eq!(gcra_admit(&params, tat, 1, 10_250), Ok(11_000));
+}
+
+#[test]
+fn gcra_charges_units_as_multiple_cells() {
+    let params = GcraParams {
+        emission_interval_ms: 1_000,
+        burst_tolerance_ms: 3_000,
+    };
+
+    assert_eq!(gcra_admit(&params, 0, 4, 5_000), Ok(9_000));
+    assert_eq!(gcra_admit(&params, 9_000, 2, 5_000), Err(2_000));
+    assert_eq!(gcra_admit(&params, 9_000, 2, 7_000), Ok(11_000));
+}
+
+#[test]
+fn gcra_overflowing_cost_waits_the_maximum() {
+    let params = GcraParams {
+        emission_interval_ms: u64::MAX,
+        burst_tolerance_ms: 0,
+    };
+
+    assert_eq!(gcra_admit(&params, i64::MAX, u64::MAX, 0), Err(u64::MAX));
+}
+
+#[test]
+fn gcra_limiter_replaces_token_bucket() {
+    let rules = GatewayRules {
+        base_price_lamports: 10,
+        bucket_capacity: 1,
+        limiter: RateLimiter::Gcra(GcraParams {
+            emission_interval_ms: 500,
+            burst_tolerance_ms: 500,
+        }),
+        ..GatewayRules::default()
+    };
+    let mut state = ConsumerRuntimeState::default();
+
+    // The empty bucket is ignored; GCRA admits two calls per second here.
+    apply_consume_units(&rules, &mut state, 1, 100, u64::MAX, 0).expect("first");
+    apply_consume_units(&rules, &mut state, 1, 100, u64::MAX, 0).expect("second");
+    assert_eq!(state.gcra_tat_ms, 101_000);
+    assert_eq!(state.bucket_tokens, 0);
+
+    let before = state;
+    assert_eq!(
+        apply_consume_units(&rules, &mut state, 1, 100, u64::MAX, 0),
+        Err(ConsumeError::RateLimited)
+    );
+    assert_eq!(state, before);
+    assert_eq!(gcra_retry_after_ms(&rules, &state, 1, 100), Some(500));
+    assert_eq!(gcra_retry_after_ms(&rules, &state, 1, 101), None);
+}
+
+#[test]
+fn congestion_window_counts_recent_gateway_calls() {
+    let mut window = SlidingWindowState {
+        period_seconds: 10,
+        window_start_ts: 0,
+        previous_used: 0,
+        current_used: 0,
+    };
+
+    assert_eq!(record_congestion(&mut window, 40, 5), 40);
+    assert_eq!(record_congestion(&mut window, 2, 9), 42);
+
+    // Halfway into the next window half of the earlier load still counts.
+    assert_eq!(record_congestion(&mut window, 1, 15), 22);
+
+    // After an idle window the load is gone.
+    assert_eq!(record_congestion(&mut window, 1, 40), 1);
+}
+
+#[test]
+fn charge_beyond_balance_accrues_debt_up_to_credit_limit() {
+    // Prepaid balance above rent is used first.
+    assert_eq!(split_charge(1_500, 1_000, 300, 0, 0), Some((300, 0)));
+    assert_eq!(split_charge(1_500, 1_000, 800, 0, 1_000), Some((500, 300)));
+
+    // Debt may reach the limit exactly, but not pass it.
+    assert_eq!(
+        split_charge(1_000, 1_000, 400, 600, 1_000),
+        Some((0, 1_000))
+    );
+    assert_eq!(split_charge(1_000, 1_000, 401, 600, 1_000), None);
+
+    // Without a credit line nothing can be owed.
+    assert_eq!(split_charge(900, 1_000, 1, 0, 0), None);
+    assert_eq!(split_charge(900, 1_000, 0, 0, 0), Some((0, 0)));
+}
+
+#[test]
+fn token_vault_balance_has_no_rent_floor() {
+    // Vault balances are charged down to zero; lamport rent stays on the PDA.
+    assert_eq!(split_charge(300, 0, 300, 0, 0), Some((300, 0)));
+    assert_eq!(split_charge(300, 0, 301, 0, 0), None);
+    assert_eq!(split_charge(300, 0, 500, 0, 200), Some((300, 200)));
+}
@@ -0,0 +1,155 @@
+use solagate::{
+    logic::dynamic_price_lamports,
+    pricing::{
+        apply_discount, unit_price_lamports, utilization_bps, volume_discount_bps,
+        volume_tiers_are_valid, PricePoint, PricingModel, VolumeDiscountTier, MAX_PRICE_POINTS,
+    },
+};
+
+fn points(points: &[(u16, u32)]) -> (u8, [PricePoint; MAX_PRICE_POINTS]) {
+    let mut out = [PricePoint::default(); MAX_PRICE_POINTS];
+    for (slot, (utilization_bps, multiplier_bps)) in out.iter_mut().zip(points) {
+        *slot = PricePoint {
+            utilization_bps: *utilization_bps,
+            multiplier_bps: *multiplier_bps,
+        };
+    }
+    (points.len() as u8, out)
+}
+
+#[test]
+fn every_model_encodes_to_the_same_length() {
+    let (count, tiers) = points(&[(5_000, 15_000), (9_000, 30_000)]);
+    let models = [
+        PricingModel::LinearSurge,
+        PricingModel::Flat,
+        PricingModel::Exponential {
+            growth_bps: 1_000,
+            step_bps: 1_000,
+        },
+        PricingModel::StepTiers { count, tiers },
+        PricingModel::PiecewiseLinear {
+            count,
+            points: tiers,
+        },
+    ];
+
+    for model in models {
+        let encoded = borsh::to_vec(&model).expect("serialize");
+        assert_eq!(encoded.len(), PricingModel::LEN);
+        assert_eq!(
+            borsh::from_slice::<PricingModel>(&encoded).expect("deserialize"),
+            model
+        );
+    }
+    assert!(borsh::to_vec(&PricingModel::default())
+        .expect("serialize")
+        .iter()
+        .all(|byte| *byte == 0));
+}
+
+#[test]
+fn linear_surge_matches_original_curve() {
+    for remaining in [100, 75, 10, 0] {
+        assert_eq!(
+            unit_price_lamports(&PricingModel::LinearSurge, 1_000, 5_000, 100, remaining),
+            dynamic_price_lamports(1_000, 100, remaining, 5_000)
+        );
+    }
+    assert_eq!(
+        unit_price_lamports(&PricingModel::Flat, 1_000, 5_000, 100, 0),
+        1_000
+    );
+}
+
+#[test]
+fn exponential_compounds_per_step() {
+    let model = PricingModel::Exponential {
+        growth_bps: 10_000,
+        step_bps: 2_500,
+    };
+
+    assert_eq!(unit_price_lamports(&model, 1_000, 0, 100, 100), 1_000);
+    assert_eq!(unit_price_lamports(&model, 1_000, 0, 100, 74), 2_000);
+    assert_eq!(unit_price_lamports(&model, 1_000, 0, 100, 50), 4_000);
+    assert_eq!(unit_price_lamports(&model, 1_000, 0, 100, 0), 16_000);
+}
+
+#[test]
+fn step_tiers_use_highest_reached_band() {
+    let (count, tiers) = points(&[(5_000, 15_000), (9_000, 30_000)]);
+    let model = PricingModel::StepTiers { count, tiers };
+
+    assert_eq!(unit_price_lamports(&model, 1_000, 0, 100, 60), 1_000);
+    assert_eq!(unit_price_lamports(&model, 1_000, 0, 100, 50), 1_500);
+    assert_eq!(unit_price_lamports(&model, 1_000, 0, 100, 11), 1_500);
+    assert_eq!(unit_price_lamports(&model, 1_000, 0, 100, 10), 3_000);
+}
+
+#[test]
+fn piecewise_linear_interpolates_between_points() {
+    let (count, points) = points(&[(2_000, 10_000), (6_000, 20_000), (10_000, 60_000)]);
+    let model = PricingModel::PiecewiseLinear { count, points };
+
+    assert_eq!(unit_price_lamports(&model, 1_000, 0, 100, 100), 1_000);
+    assert_eq!(unit_price_lamports(&model, 1_000, 0, 100, 60), 1_500);
+    assert_eq!(unit_price_lamports(&model, 1_000, 0, 100, 40), 2_000);
+    assert_eq!(unit_price_lamports(&model, 1_000, 0, 100, 20), 4_000);
+    assert_eq!(unit_price_lamports(&model, 1_000, 0, 100, 0), 6_000);
+}
+
+#[test]
+fn schedules_must_be_ascending_and_in_range() {
+    let (count, ascending) = points(&[(1_000, 12_000), (8_000, 20_000)]);
+    assert!(PricingModel::StepTiers {
+        count,
+        tiers: ascending
+    }
+    .is_valid());
+
+    let (count, descending) = points(&[(8_000, 12_000), (1_000, 20_000)]);
+    assert!(!PricingModel::PiecewiseLinear {
+        count,
+        points: descending
+    }
+    .is_valid());
+
+    let (count, out_of_range) = points(&[(10_001, 12_000)]);
+    assert!(!PricingModel::StepTiers {
+        count,
+        tiers: out_of_range
+    }
+    .is_valid());
+
+    assert!(!PricingModel::Exponential {
+        growth_bps: 100,
+        step_bps: 1
+    }
+    .is_valid());
+    assert_eq!(utilization_bps(0, 0), 0);
+}
+
+#[test]
+fn volume_discount_uses_highest_reached_tier() {
+    let tiers = [
+        VolumeDiscountTier {
+            min_total_calls: 1_000,
+            discount_bps: 500,
+        },
+        VolumeDiscountTier {
+            min_total_calls: 1_000_000,
+            discount_bps: 1_000,
+        },
+    ];
+
+    assert!(volume_tiers_are_valid(&tiers));
+    assert_eq!(volume_discount_bps(&tiers, 999), 0);
+    assert_eq!(volume_discount_bps(&tiers, 1_000), 500);
+    assert_eq!(volume_discount_bps(&tiers, 5_000_000), 1_000);
+    assert_eq!(apply_discount(1_000, 1_000), 900);
+    assert_eq!(apply_discount(u64::MAX, 10_000), 0);
+
+    let unordered = [tiers[1], tiers[0]];
+    assert!(!volume_tiers_are_valid(&unordered));
+    assert!(!volume_tiers_are_valid(&[VolumeDiscountTier::default()]));
+}
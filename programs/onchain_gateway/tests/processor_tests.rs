@@ -0,0 +1,789 @@
+#![allow(deprecated)]
+
+use std::{cell::RefCell, sync::Once};
+
+use solagate::{
+    error::GatewayError,
+    instruction::{BatchConsumeEntry, BatchConsumeOutcome, GatewayInstruction},
+    layout::{VersionedLayout, HEADER_LEN},
+    processor::process_instruction,
+    state::{
+        gateway_stats_pda, plan_pda, route_policy_pda, BackendSigner, ConsumerAccount,
+        ConsumerStatus, GatewayConfig, GatewayStats, Plan, RoutePolicy,
+    },
+    ID,
+};
+use solana_program::{
+    account_info::AccountInfo,
+    clock::Clock,
+    entrypoint::{deserialize, ProgramResult, MAX_PERMITTED_DATA_INCREASE, NON_DUP_MARKER},
+    instruction::Instruction,
+    program_error::ProgramError,
+    program_stubs::{set_syscall_stubs, SyscallStubs},
+    pubkey::Pubkey,
+    rent::Rent,
+    system_program,
+};
+use spl_token_2022::{
+    extension::{
+        transfer_fee::{
+            instruction::harvest_withheld_tokens_to_mint, TransferFee, TransferFeeAmount,
+            TransferFeeConfig,
+        },
+        BaseStateWithExtensionsMut, ExtensionType, StateWithExtensionsMut,
+    },
+    state::{Account as TokenAccount, AccountState, Mint},
+};
+
+const NOW_TS: i64 = 1_000;
+
+thread_local! {
+    /// Cross-program invocations made by the current test, in order.
+    static INVOKED: RefCell<Vec<Instruction>> = const { RefCell::new(Vec::new()) };
+    /// Return data set by the current test's last instruction.
+    static RETURN_DATA: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Host stand-ins for the runtime: fixed sysvars, and CPIs and return data that are recorded.
+/// System transfers also move the lamports.
+struct TestRuntime;
+
+impl SyscallStubs for TestRuntime {
+    fn sol_log(&self, _message: &str) {}
+
+    fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+        let clock = Clock {
+            unix_timestamp: NOW_TS,
+            epoch: 1,
+            ..Clock::default()
+        };
+        unsafe { *(var_addr as *mut Clock) = clock };
+        0
+    }
+
+    fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+        unsafe { *(var_addr as *mut Rent) = Rent::default() };
+        0
+    }
+
+    fn sol_set_return_data(&self, data: &[u8]) {
+        RETURN_DATA.with(|return_data| *return_data.borrow_mut() = data.to_vec());
+    }
+
+    fn sol_invoke_signed(
+        &self,
+        instruction: &Instruction,
+        account_infos: &[AccountInfo],
+        _signers_seeds: &[&[&[u8]]],
+    ) -> ProgramResult {
+        INVOKED.with(|invoked| invoked.borrow_mut().push(instruction.clone()));
+        if instruction.program_id == system_program::ID && instruction.data[..4] == [2, 0, 0, 0] {
+            let lamports = u64::from_le_bytes(instruction.data[4..12].try_into().unwrap());
+            let find = |key: &Pubkey| account_infos.iter().find(|info| info.key == key).unwrap();
+            **find(&instruction.accounts[0].pubkey).try_borrow_mut_lamports()? -= lamports;
+            **find(&instruction.accounts[1].pubkey).try_borrow_mut_lamports()? += lamports;
+        }
+        Ok(())
+    }
+}
+
+fn invoked() -> Vec<Instruction> {
+    INVOKED.with(|invoked| invoked.borrow().clone())
+}
+
+fn return_data() -> Vec<u8> {
+    RETURN_DATA.with(|return_data| return_data.borrow().clone())
+}
+
+fn install_runtime() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        set_syscall_stubs(Box::new(TestRuntime));
+    });
+    INVOKED.with(|invoked| invoked.borrow_mut().clear());
+    RETURN_DATA.with(|return_data| return_data.borrow_mut().clear());
+}
+
+#[derive(Debug, Clone)]
+struct TestAccount {
+    key: Pubkey,
+    owner: Pubkey,
+    lamports: u64,
+    data: Vec<u8>,
+    is_signer: bool,
+    is_writable: bool,
+}
+
+impl TestAccount {
+    fn new(owner: Pubkey, data: Vec<u8>) -> Self {
+        Self {
+            key: Pubkey::new_unique(),
+            owner,
+            lamports: Rent::default().minimum_balance(data.len()),
+            data,
+            is_signer: false,
+            is_writable: true,
+        }
+    }
+
+    fn signer() -> Self {
+        Self {
+            is_signer: true,
+            lamports: 1_000_000_000,
+            ..Self::new(system_program::ID, Vec::new())
+        }
+    }
+
+    fn program(key: Pubkey) -> Self {
+        Self {
+            key,
+            is_writable: false,
+            ..Self::new(Pubkey::default(), Vec::new())
+        }
+    }
+}
+
+/// Runs `instruction` through the entrypoint input format, so accounts can be
+/// reallocated, and copies the resulting balances and data back.
+fn run(accounts: &mut [TestAccount], instruction: &GatewayInstruction) -> ProgramResult {
+    install_runtime();
+
+    let mut input = Vec::new();
+    input.extend_from_slice(&(accounts.len() as u64).to_le_bytes());
+    for account in accounts.iter() {
+        input.extend_from_slice(&[
+            NON_DUP_MARKER,
+            account.is_signer as u8,
+            account.is_writable as u8,
+            0,
+            0,
+            0,
+            0,
+            0,
+        ]);
+        input.extend_from_slice(account.key.as_ref());
+        input.extend_from_slice(account.owner.as_ref());
+        input.extend_from_slice(&account.lamports.to_le_bytes());
+        input.extend_from_slice(&(account.data.len() as u64).to_le_bytes());
+        input.extend_from_slice(&account.data);
+        input.resize(input.len() + MAX_PERMITTED_DATA_INCREASE, 0);
+        input.resize(input.len().next_multiple_of(8), 0);
+        input.extend_from_slice(&0u64.to_le_bytes());
+    }
+    let data = instruction.pack().expect("serialize");
+    input.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    input.extend_from_slice(&data);
+    input.extend_from_slice(ID.as_ref());
+
+    // The entrypoint expects an 8-byte aligned buffer.
+    let mut buffer = vec![0u64; input.len().div_ceil(8)];
+    let bytes =
+        unsafe { std::slice::from_raw_parts_mut(buffer.as_mut_ptr() as *mut u8, input.len()) };
+    bytes.copy_from_slice(&input);
+
+    let (program_id, infos, instruction_data) = unsafe { deserialize(bytes.as_mut_ptr()) };
+    let result = process_instruction(program_id, &infos, instruction_data);
+    for (account, info) in accounts.iter_mut().zip(&infos) {
+        account.lamports = info.lamports();
+        account.owner = *info.owner;
+        account.data = info.data.borrow().to_vec();
+    }
+    result
+}
+
+fn baseline_gateway(admin: &Pubkey) -> Vec<u8> {
+    let mut data = vec![1u8];
+    data.extend_from_slice(admin.as_ref());
+    data.extend_from_slice(Pubkey::new_unique().as_ref());
+    data.extend_from_slice(Pubkey::new_unique().as_ref());
+    data.extend_from_slice(&1_000u64.to_le_bytes());
+    data.extend_from_slice(&0u16.to_le_bytes());
+    for value in [100u64, 60, 10, 1] {
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+    data.push(255);
+    assert_eq!(data.len(), GatewayConfig::BASELINE_LEN);
+    data
+}
+
+fn baseline_consumer(gateway: &Pubkey, owner: &Pubkey) -> Vec<u8> {
+    let mut data = vec![1u8];
+    data.extend_from_slice(gateway.as_ref());
+    data.extend_from_slice(owner.as_ref());
+    data.extend_from_slice(&1u64.to_le_bytes());
+    data.extend_from_slice(&[3u8; 32]);
+    data.resize(ConsumerAccount::BASELINE_LEN - 1, 0);
+    data.push(254);
+    data
+}
+
+#[test]
+fn baseline_consumers_count_themselves_as_they_migrate() {
+    let admin = TestAccount::signer();
+    let mut gateway = TestAccount::new(ID, baseline_gateway(&admin.key));
+    gateway.key = Pubkey::new_unique();
+    let consumers = [
+        TestAccount::new(ID, baseline_consumer(&gateway.key, &Pubkey::new_unique())),
+        TestAccount::new(ID, baseline_consumer(&gateway.key, &Pubkey::new_unique())),
+    ];
+    let migrate_consumer = |consumer: &TestAccount, gateway: &TestAccount| {
+        let mut accounts = vec![
+            TestAccount::signer(),
+            consumer.clone(),
+            TestAccount::program(system_program::ID),
+            gateway.clone(),
+        ];
+        run(&mut accounts, &GatewayInstruction::MigrateAccount).map(|()| accounts)
+    };
+
+    // The gateway has to be migrated before its consumers can be counted.
+    assert_eq!(
+        migrate_consumer(&consumers[0], &gateway).map(|_| ()),
+        Err(GatewayError::AccountNeedsMigration.into())
+    );
+
+    // Anyone can migrate the gateway; it starts without consumers.
+    let mut accounts = vec![
+        TestAccount::signer(),
+        gateway,
+        TestAccount::program(system_program::ID),
+    ];
+    run(&mut accounts, &GatewayInstruction::MigrateAccount).expect("migrate gateway");
+    let mut gateway = accounts[1].clone();
+    assert_eq!(gateway.data.len(), GatewayConfig::LEN);
+    assert_eq!(
+        gateway.lamports,
+        Rent::default().minimum_balance(GatewayConfig::LEN)
+    );
+    let cfg = borsh::from_slice::<GatewayConfig>(&gateway.data).expect("decode");
+    assert_eq!(cfg.version, GatewayConfig::VERSION);
+    assert_eq!(cfg.admin, admin.key);
+    assert_eq!(cfg.creator, admin.key);
+    assert_eq!(cfg.consumer_count, 0);
+
+    // Consumers of another gateway cannot inflate its count.
+    let mut other = gateway.clone();
+    other.key = Pubkey::new_unique();
+    assert_eq!(
+        migrate_consumer(&consumers[0], &other).map(|_| ()),
+        Err(GatewayError::InvalidAccount.into())
+    );
+
+    for (count, consumer) in consumers.iter().enumerate() {
+        let migrated = migrate_consumer(consumer, &gateway).expect("migrate consumer");
+        assert_eq!(
+            migrated[1].data[..HEADER_LEN - 1],
+            ConsumerAccount::DISCRIMINATOR
+        );
+        assert_eq!(migrated[1].data.len(), ConsumerAccount::LEN);
+        gateway = migrated[3].clone();
+        let cfg = borsh::from_slice::<GatewayConfig>(&gateway.data).expect("decode");
+        assert_eq!(cfg.consumer_count, count as u64 + 1);
+    }
+}
+
+#[test]
+fn consumers_of_a_closed_gateway_can_migrate_and_close() {
+    let owner = TestAccount::signer();
+    let closed_gateway = TestAccount {
+        lamports: 0,
+        ..TestAccount::new(system_program::ID, Vec::new())
+    };
+    let consumer = TestAccount::new(ID, baseline_consumer(&closed_gateway.key, &owner.key));
+
+    let mut accounts = vec![
+        TestAccount::signer(),
+        consumer,
+        TestAccount::program(system_program::ID),
+        closed_gateway.clone(),
+    ];
+    run(&mut accounts, &GatewayInstruction::MigrateAccount).expect("migrate consumer");
+
+    let mut accounts = vec![owner, accounts[1].clone(), closed_gateway];
+    let balance = accounts[0].lamports + accounts[1].lamports;
+    run(&mut accounts, &GatewayInstruction::CloseConsumer).expect("close consumer");
+    assert_eq!(accounts[0].lamports, balance);
+    assert_eq!(accounts[1].lamports, 0);
+}
+
+#[test]
+fn migrating_v1_stats_keeps_its_counters() {
+    let stats = GatewayStats {
+        discriminator: GatewayStats::DISCRIMINATOR,
+        version: GatewayStats::VERSION,
+        is_initialized: true,
+        gateway: Pubkey::new_unique(),
+        period_limit: 500,
+        period_seconds: 60,
+        quota_remaining: 120,
+        bump: 253,
+        ..GatewayStats::default()
+    };
+    let current = borsh::to_vec(&stats).expect("serialize");
+    let mut v1 = current[..GatewayStats::V1_LEN].to_vec();
+    v1[8] = 1;
+
+    let mut accounts = vec![
+        TestAccount::signer(),
+        TestAccount::new(ID, v1),
+        TestAccount::program(system_program::ID),
+    ];
+    run(&mut accounts, &GatewayInstruction::MigrateAccount).expect("migrate stats");
+    assert_eq!(accounts[1].data, current);
+    assert_eq!(
+        accounts[1].lamports,
+        Rent::default().minimum_balance(GatewayStats::LEN)
+    );
+}
+
+#[test]
+fn unknown_account_sizes_are_not_migrated() {
+    let mut accounts = vec![
+        TestAccount::signer(),
+        TestAccount::new(ID, vec![1u8; 99]),
+        TestAccount::program(system_program::ID),
+    ];
+    assert_eq!(
+        run(&mut accounts, &GatewayInstruction::MigrateAccount),
+        Err(GatewayError::InvalidAccount.into())
+    );
+}
+
+fn current_gateway(admin: &Pubkey) -> Vec<u8> {
+    borsh::to_vec(&GatewayConfig {
+        discriminator: GatewayConfig::DISCRIMINATOR,
+        version: GatewayConfig::VERSION,
+        is_initialized: true,
+        admin: *admin,
+        creator: *admin,
+        ..GatewayConfig::default()
+    })
+    .expect("serialize")
+}
+
+#[test]
+fn gateway_cannot_close_while_a_plan_is_open() {
+    let admin = TestAccount::signer();
+    let gateway = TestAccount::new(ID, current_gateway(&admin.key));
+    let mut plan = TestAccount::new(ID, vec![0u8; Plan::LEN]);
+    plan.key = plan_pda(&gateway.key, 1, &ID).0;
+
+    let mut accounts = vec![
+        admin,
+        gateway,
+        plan,
+        TestAccount::program(system_program::ID),
+    ];
+    let set_plan = GatewayInstruction::SetPlan {
+        plan_id: 1,
+        base_price_lamports: 1_000,
+        max_surge_bps: 0,
+        period_limit: 0,
+        period_seconds: 0,
+        bucket_capacity: 0,
+        refill_per_second: 0,
+    };
+    run(&mut accounts, &set_plan).expect("set plan");
+    // Updating an existing plan does not count it twice.
+    run(&mut accounts, &set_plan).expect("update plan");
+    let cfg = borsh::from_slice::<GatewayConfig>(&accounts[1].data).expect("decode");
+    assert_eq!(cfg.plan_count, 1);
+
+    let mut close_gateway = accounts[..2].to_vec();
+    assert_eq!(
+        run(&mut close_gateway, &GatewayInstruction::CloseGateway),
+        Err(GatewayError::PoliciesOutstanding.into())
+    );
+
+    let mut close_plan = accounts[..3].to_vec();
+    run(
+        &mut close_plan,
+        &GatewayInstruction::ClosePlan { plan_id: 1 },
+    )
+    .expect("close plan");
+    let mut close_gateway = close_plan[..2].to_vec();
+    run(&mut close_gateway, &GatewayInstruction::CloseGateway).expect("close gateway");
+    assert_eq!(close_gateway[1].lamports, 0);
+}
+
+/// Token-2022 mint withholding 1% of every transfer, capped at 5 000 base units.
+fn fee_mint_data() -> Vec<u8> {
+    let len = ExtensionType::try_calculate_account_len::<Mint>(&[ExtensionType::TransferFeeConfig])
+        .expect("mint len");
+    let mut data = vec![0u8; len];
+    let mut mint =
+        StateWithExtensionsMut::<Mint>::unpack_uninitialized(&mut data).expect("uninitialized");
+    let fee = TransferFee {
+        epoch: 0.into(),
+        maximum_fee: 5_000.into(),
+        transfer_fee_basis_points: 100.into(),
+    };
+    let config = mint
+        .init_extension::<TransferFeeConfig>(true)
+        .expect("extension");
+    config.older_transfer_fee = fee;
+    config.newer_transfer_fee = fee;
+    mint.base = Mint {
+        decimals: 6,
+        is_initialized: true,
+        ..Mint::default()
+    };
+    mint.pack_base();
+    mint.init_account_type().expect("account type");
+    data
+}
+
+fn fee_token_account_data(
+    mint: &Pubkey,
+    authority: &Pubkey,
+    amount: u64,
+    withheld: u64,
+) -> Vec<u8> {
+    let len = ExtensionType::try_calculate_account_len::<TokenAccount>(&[
+        ExtensionType::TransferFeeAmount,
+    ])
+    .expect("account len");
+    let mut data = vec![0u8; len];
+    let mut account = StateWithExtensionsMut::<TokenAccount>::unpack_uninitialized(&mut data)
+        .expect("uninitialized");
+    account
+        .init_extension::<TransferFeeAmount>(true)
+        .expect("extension")
+        .withheld_amount = withheld.into();
+    account.base = TokenAccount {
+        mint: *mint,
+        owner: *authority,
+        amount,
+        state: AccountState::Initialized,
+        ..TokenAccount::default()
+    };
+    account.pack_base();
+    account.init_account_type().expect("account type");
+    data
+}
+
+#[test]
+fn closing_consumer_harvests_withheld_fees_before_closing_the_vault() {
+    let owner = TestAccount::signer();
+    let mint = TestAccount::new(spl_token_2022::ID, fee_mint_data());
+    let mut gateway = TestAccount::new(ID, Vec::new());
+    let mut consumer = TestAccount::new(ID, vec![0u8; ConsumerAccount::LEN]);
+    let vault = TestAccount::new(
+        spl_token_2022::ID,
+        fee_token_account_data(&mint.key, &consumer.key, 0, 40),
+    );
+    gateway.data = borsh::to_vec(&GatewayConfig {
+        billing_mint: mint.key,
+        consumer_count: 1,
+        ..borsh::from_slice(&current_gateway(&Pubkey::new_unique())).expect("decode")
+    })
+    .expect("serialize");
+    consumer.data = borsh::to_vec(&ConsumerAccount {
+        discriminator: ConsumerAccount::DISCRIMINATOR,
+        version: ConsumerAccount::VERSION,
+        is_initialized: true,
+        gateway: gateway.key,
+        owner: owner.key,
+        token_vault: vault.key,
+        ..ConsumerAccount::default()
+    })
+    .expect("serialize");
+
+    let mut accounts = vec![
+        owner.clone(),
+        consumer.clone(),
+        gateway,
+        vault.clone(),
+        mint.clone(),
+        TestAccount::program(spl_token_2022::ID),
+    ];
+    run(&mut accounts, &GatewayInstruction::CloseConsumer).expect("close consumer");
+
+    let harvest = harvest_withheld_tokens_to_mint(&spl_token_2022::ID, &mint.key, &[&vault.key])
+        .expect("harvest");
+    let close = spl_token_2022::instruction::close_account(
+        &spl_token_2022::ID,
+        &vault.key,
+        &owner.key,
+        &consumer.key,
+        &[],
+    )
+    .expect("close");
+    assert_eq!(invoked(), vec![harvest, close]);
+    assert_eq!(accounts[1].lamports, 0);
+}
+
+#[test]
+fn token_charge_counts_only_what_reaches_the_treasury() {
+    let backend = TestAccount::signer();
+    let owner = Pubkey::new_unique();
+    let mint = TestAccount::new(spl_token_2022::ID, fee_mint_data());
+    let treasury = TestAccount::new(
+        spl_token_2022::ID,
+        fee_token_account_data(&mint.key, &Pubkey::new_unique(), 0, 0),
+    );
+    let mut gateway = TestAccount::new(ID, Vec::new());
+    let mut consumer = TestAccount::new(ID, vec![0u8; ConsumerAccount::LEN]);
+    let vault = TestAccount::new(
+        spl_token_2022::ID,
+        fee_token_account_data(&mint.key, &consumer.key, 1_000_000, 0),
+    );
+
+    let mut backend_signers = [BackendSigner::default(); 4];
+    backend_signers[0].signer = backend.key;
+    gateway.data = borsh::to_vec(&GatewayConfig {
+        treasury: treasury.key,
+        backend_signers,
+        base_price_lamports: 10_000,
+        billing_mint: mint.key,
+        billing_decimals: 6,
+        ..borsh::from_slice(&current_gateway(&Pubkey::new_unique())).expect("decode")
+    })
+    .expect("serialize");
+    consumer.data = borsh::to_vec(&ConsumerAccount {
+        discriminator: ConsumerAccount::DISCRIMINATOR,
+        version: ConsumerAccount::VERSION,
+        is_initialized: true,
+        gateway: gateway.key,
+        owner,
+        api_key_id: 7,
+        api_key_hash: [5u8; 32],
+        token_vault: vault.key,
+        ..ConsumerAccount::default()
+    })
+    .expect("serialize");
+
+    let mut accounts = vec![
+        backend,
+        gateway,
+        consumer,
+        treasury,
+        vault,
+        mint,
+        TestAccount::program(spl_token_2022::ID),
+    ];
+    let consume = GatewayInstruction::Consume {
+        api_key_id: 7,
+        presented_api_key_hash: [5u8; 32],
+        units: 1,
+        route_id: None,
+    };
+    run(&mut accounts, &consume).expect("consume");
+
+    // The mint withholds 1% of the 10 000 charged.
+    let consumer = borsh::from_slice::<ConsumerAccount>(&accounts[2].data).expect("decode");
+    assert_eq!(consumer.total_calls, 1);
+    assert_eq!(consumer.total_spent_lamports, 9_900);
+}
+
+#[test]
+fn enabling_route_limits_starts_with_full_counters() {
+    let admin = TestAccount::signer();
+    let gateway = TestAccount::new(ID, current_gateway(&admin.key));
+    let mut route = TestAccount::new(ID, vec![0u8; RoutePolicy::LEN]);
+    route.key = route_policy_pda(&gateway.key, 4, &ID).0;
+
+    let mut accounts = vec![
+        admin,
+        gateway,
+        route,
+        TestAccount::program(system_program::ID),
+    ];
+    let set_route = |period_limit, bucket_capacity| GatewayInstruction::SetRoutePolicy {
+        route_id: 4,
+        base_price_lamports: 1_000,
+        max_surge_bps: 0,
+        period_limit,
+        period_seconds: 60,
+        bucket_capacity,
+        refill_per_second: 1,
+    };
+    run(&mut accounts, &set_route(0, 0)).expect("create route");
+    run(&mut accounts, &set_route(10, 5)).expect("enable limits");
+
+    let route = borsh::from_slice::<RoutePolicy>(&accounts[2].data).expect("decode");
+    assert_eq!(route.quota_remaining, 10);
+    assert_eq!(route.quota_period_start_ts, NOW_TS);
+    assert_eq!(route.bucket_tokens, 5);
+}
+
+#[test]
+fn gateway_capacity_changes_apply_to_current_counters() {
+    let admin = TestAccount::signer();
+    let gateway = TestAccount::new(ID, current_gateway(&admin.key));
+    let mut stats = TestAccount::new(ID, vec![0u8; GatewayStats::LEN]);
+    stats.key = gateway_stats_pda(&gateway.key, &ID).0;
+
+    let mut accounts = vec![
+        admin,
+        gateway,
+        stats,
+        TestAccount::program(system_program::ID),
+    ];
+    let set_capacity = |period_limit, bucket_capacity| GatewayInstruction::SetGatewayCapacity {
+        period_limit,
+        period_seconds: 3_600,
+        bucket_capacity,
+        refill_per_second: 1,
+    };
+    let stored = |accounts: &[TestAccount]| {
+        borsh::from_slice::<GatewayStats>(&accounts[2].data).expect("decode")
+    };
+
+    // Registered without limits, then enabled: the counters start full.
+    run(&mut accounts, &set_capacity(0, 0)).expect("register stats");
+    run(&mut accounts, &set_capacity(100, 20)).expect("enable capacity");
+    let mut stats = stored(&accounts);
+    assert_eq!(stats.quota_remaining, 100);
+    assert_eq!(stats.bucket_tokens, 20);
+
+    // An emergency cut below the 70 units already used blocks at once.
+    stats.quota_remaining = 30;
+    accounts[2].data = borsh::to_vec(&stats).expect("serialize");
+    run(&mut accounts, &set_capacity(50, 10)).expect("cut capacity");
+    let stats = stored(&accounts);
+    assert_eq!(stats.quota_remaining, 0);
+    assert_eq!(stats.bucket_tokens, 10);
+}
+
+#[test]
+fn token_billing_consumer_without_vault_cannot_top_up_lamports() {
+    let owner = TestAccount::signer();
+    let mut gateway = TestAccount::new(ID, Vec::new());
+    let mut consumer = TestAccount::new(ID, Vec::new());
+    gateway.data = borsh::to_vec(&GatewayConfig {
+        billing_mint: Pubkey::new_unique(),
+        ..borsh::from_slice(&current_gateway(&Pubkey::new_unique())).expect("decode")
+    })
+    .expect("serialize");
+    consumer.data = borsh::to_vec(&ConsumerAccount {
+        discriminator: ConsumerAccount::DISCRIMINATOR,
+        version: ConsumerAccount::VERSION,
+        is_initialized: true,
+        gateway: gateway.key,
+        owner: owner.key,
+        ..ConsumerAccount::default()
+    })
+    .expect("serialize");
+
+    let mut accounts = vec![
+        owner,
+        consumer,
+        gateway,
+        TestAccount::program(system_program::ID),
+    ];
+    let top_up = GatewayInstruction::TopUp { lamports: 1_000 };
+    assert_eq!(
+        run(&mut accounts, &top_up),
+        Err(GatewayError::InvalidAccount.into())
+    );
+    assert!(invoked().is_empty());
+
+    // The same consumer on a lamport gateway is funded directly.
+    accounts[2].data = current_gateway(&Pubkey::new_unique());
+    let before = accounts[1].lamports;
+    run(&mut accounts, &top_up).expect("top up");
+    assert_eq!(accounts[1].lamports, before + 1_000);
+}
+
+#[test]
+fn revoked_consumer_cannot_close_to_register_again() {
+    let owner = TestAccount::signer();
+    let mut gateway = TestAccount::new(ID, Vec::new());
+    let mut consumer = TestAccount::new(ID, Vec::new());
+    gateway.data = borsh::to_vec(&GatewayConfig {
+        consumer_count: 1,
+        ..borsh::from_slice(&current_gateway(&Pubkey::new_unique())).expect("decode")
+    })
+    .expect("serialize");
+    let stored = |status| ConsumerAccount {
+        discriminator: ConsumerAccount::DISCRIMINATOR,
+        version: ConsumerAccount::VERSION,
+        is_initialized: true,
+        gateway: gateway.key,
+        owner: owner.key,
+        status,
+        ..ConsumerAccount::default()
+    };
+
+    for status in [ConsumerStatus::Suspended, ConsumerStatus::Revoked] {
+        consumer.data = borsh::to_vec(&stored(status)).expect("serialize");
+        let mut accounts = vec![owner.clone(), consumer.clone(), gateway.clone()];
+        assert_eq!(
+            run(&mut accounts, &GatewayInstruction::CloseConsumer),
+            Err(GatewayError::ConsumerSuspended.into()),
+            "{status:?}"
+        );
+        assert_eq!(accounts[1].data, consumer.data);
+    }
+
+    consumer.data = borsh::to_vec(&stored(ConsumerStatus::Active)).expect("serialize");
+    let mut accounts = vec![owner, consumer, gateway];
+    run(&mut accounts, &GatewayInstruction::CloseConsumer).expect("close consumer");
+    assert_eq!(accounts[1].lamports, 0);
+}
+
+#[test]
+fn gcra_rejections_return_the_wait() {
+    let backend = TestAccount::signer();
+    let treasury = TestAccount::signer();
+    let mut gateway = TestAccount::new(ID, Vec::new());
+    let mut consumer = TestAccount::new(ID, Vec::new());
+
+    let mut backend_signers = [BackendSigner::default(); 4];
+    backend_signers[0].signer = backend.key;
+    gateway.data = borsh::to_vec(&GatewayConfig {
+        treasury: treasury.key,
+        backend_signers,
+        gcra_emission_interval_ms: 1_000,
+        ..borsh::from_slice(&current_gateway(&Pubkey::new_unique())).expect("decode")
+    })
+    .expect("serialize");
+    // The previous call was admitted 2.5 s ahead of the clock.
+    consumer.data = borsh::to_vec(&ConsumerAccount {
+        discriminator: ConsumerAccount::DISCRIMINATOR,
+        version: ConsumerAccount::VERSION,
+        is_initialized: true,
+        gateway: gateway.key,
+        owner: Pubkey::new_unique(),
+        api_key_id: 7,
+        api_key_hash: [5u8; 32],
+        gcra_tat_ms: NOW_TS * 1_000 + 2_500,
+        ..ConsumerAccount::default()
+    })
+    .expect("serialize");
+
+    let mut accounts = vec![backend, gateway, consumer, treasury];
+    let consume = GatewayInstruction::Consume {
+        api_key_id: 7,
+        presented_api_key_hash: [5u8; 32],
+        units: 1,
+        route_id: None,
+    };
+    assert_eq!(
+        run(&mut accounts, &consume),
+        Err(GatewayError::RateLimited.into())
+    );
+    assert_eq!(return_data(), 2_500u64.to_le_bytes());
+
+    // In a batch the wait is part of the entry's outcome.
+    accounts.swap(2, 3);
+    let batch = GatewayInstruction::ConsumeBatch {
+        entries: vec![BatchConsumeEntry {
+            api_key_id: 7,
+            presented_api_key_hash: [5u8; 32],
+            units: 1,
+            route_id: None,
+        }],
+    };
+    run(&mut accounts, &batch).expect("batch");
+    let outcomes: Vec<BatchConsumeOutcome> = borsh::from_slice(&return_data()).expect("decode");
+    assert_eq!(
+        outcomes,
+        vec![BatchConsumeOutcome {
+            charged_lamports: 0,
+            error_code: u64::from(ProgramError::from(GatewayError::RateLimited)),
+            retry_after_ms: 2_500,
+        }]
+    );
+}